@@ -0,0 +1,684 @@
+use crate::jobconfig::{BinaryConfig, Sources};
+use failure::{format_err, Fallible, ResultExt};
+use ignore::WalkBuilder;
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::{self, Read},
+    iter,
+    path::{Component, Path, PathBuf},
+    process,
+};
+
+/// The archive formats of the transferred files
+///
+/// The provider needs the matching tools to unpack or create the compressed
+/// archives: `gzip`, `xz`, `zstd`, `zip` or `unzip`.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ArchiveFormat {
+    #[serde(rename = "tar")]
+    Tar,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "tar.xz")]
+    TarXz,
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Guesses the format from the file name, defaulting to a plain tarball
+    pub fn from_path(path: &Path) -> Self {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let has_ext = |exts: &[&str]| exts.iter().any(|ext| name.ends_with(ext));
+        if has_ext(&[".tar.gz", ".tgz"]) {
+            ArchiveFormat::TarGz
+        } else if has_ext(&[".tar.xz", ".txz"]) {
+            ArchiveFormat::TarXz
+        } else if has_ext(&[".tar.zst", ".tzst"]) {
+            ArchiveFormat::TarZst
+        } else if has_ext(&[".zip"]) {
+            ArchiveFormat::Zip
+        } else {
+            ArchiveFormat::Tar
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ArchiveFormat::Tar => "tar",
+            ArchiveFormat::TarGz => "tar.gz",
+            ArchiveFormat::TarXz => "tar.xz",
+            ArchiveFormat::TarZst => "tar.zst",
+            ArchiveFormat::Zip => "zip",
+        }
+    }
+
+    /// Returns: the shell command unpacking `archive` into the `destination` directory
+    ///
+    /// The paths have to be quoted already.
+    pub fn extract_cmd(self, archive: &str, destination: &str) -> String {
+        match self {
+            ArchiveFormat::Tar => format!("tar -xf {} -C {}", archive, destination),
+            ArchiveFormat::TarGz => format!("tar -xzf {} -C {}", archive, destination),
+            ArchiveFormat::TarXz => format!("tar -xJf {} -C {}", archive, destination),
+            ArchiveFormat::TarZst => {
+                format!("zstd -dcq {} | tar -xf - -C {}", archive, destination)
+            }
+            ArchiveFormat::Zip => format!("unzip -qo {} -d {}", archive, destination),
+        }
+    }
+
+    /// Returns: the shell command failing with a message if a tool needed by
+    /// `extract_cmd` is missing on the provider
+    pub fn extract_check_cmd(self) -> String {
+        let tools: &[&str] = match self {
+            ArchiveFormat::Tar => &["tar"],
+            ArchiveFormat::TarGz => &["tar", "gzip"],
+            ArchiveFormat::TarXz => &["tar", "xz"],
+            ArchiveFormat::TarZst => &["tar", "zstd"],
+            ArchiveFormat::Zip => &["unzip"],
+        };
+        self.require_cmd(tools)
+    }
+
+    /// Returns: the shell command failing with a message if a tool needed by
+    /// `compress_cmd` or `compress_list_cmd` is missing on the provider
+    pub fn compress_check_cmd(self) -> String {
+        let tools: &[&str] = match self {
+            ArchiveFormat::Tar => &["tar"],
+            ArchiveFormat::TarGz => &["tar", "gzip"],
+            ArchiveFormat::TarXz => &["tar", "xz"],
+            ArchiveFormat::TarZst => &["tar", "zstd"],
+            ArchiveFormat::Zip => &["zip"],
+        };
+        self.require_cmd(tools)
+    }
+
+    /// The archives are always verified with `sha256sum`, so it's required as well
+    fn require_cmd(self, tools: &[&str]) -> String {
+        format!(
+            "for tool in {} sha256sum; do command -v $tool > /dev/null || \
+             {{ echo \"$tool is needed for the .{} archives, but it's missing \
+             in the provider image\"; exit 1; }}; done",
+            tools.join(" "),
+            self.extension()
+        )
+    }
+
+    /// Returns: the shell command packing the contents of the `source` directory
+    /// into `archive`
+    ///
+    /// The paths have to be quoted already.
+    pub fn compress_cmd(self, source: &str, archive: &str) -> String {
+        match self {
+            ArchiveFormat::Tar => format!("tar -cf {} -C {} .", archive, source),
+            ArchiveFormat::TarGz => format!("tar -czf {} -C {} .", archive, source),
+            ArchiveFormat::TarXz => format!("tar -cJf {} -C {} .", archive, source),
+            ArchiveFormat::TarZst => {
+                format!("tar -cf - -C {} . | zstd -qf -o {}", source, archive)
+            }
+            ArchiveFormat::Zip => format!("cd {} && zip -qr {} .", source, archive),
+        }
+    }
+
+    /// Returns: the shell command packing the files listed in the `list` file,
+    /// relative to `source`, into `archive`
+    ///
+    /// The paths have to be quoted already.
+    pub fn compress_list_cmd(self, source: &str, list: &str, archive: &str) -> String {
+        match self {
+            ArchiveFormat::Tar => format!("tar -cf {} -C {} -T {}", archive, source, list),
+            ArchiveFormat::TarGz => format!("tar -czf {} -C {} -T {}", archive, source, list),
+            ArchiveFormat::TarXz => format!("tar -cJf {} -C {} -T {}", archive, source, list),
+            ArchiveFormat::TarZst => format!(
+                "tar -cf - -C {} -T {} | zstd -qf -o {}",
+                source, list, archive
+            ),
+            ArchiveFormat::Zip => format!("cd {} && zip -q {} -@ < {}", source, archive, list),
+        }
+    }
+}
+
+/// What to do with the files which already exist in the extraction directory
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OnConflict {
+    Overwrite,
+    Skip,
+    Fail,
+}
+
+impl Default for OnConflict {
+    fn default() -> Self {
+        OnConflict::Fail
+    }
+}
+
+/// The number of the entries extracted from an archive
+#[derive(Debug, Default, PartialEq)]
+pub struct Extracted {
+    pub files: usize,
+    /// the entries skipped because they already existed or aren't supported
+    pub skipped: usize,
+}
+
+/// Unpacks the archive into the `destination` directory
+///
+/// The entries leading outside of `destination`, either by their path or
+/// by the target of a link, are rejected. With `OnConflict::Fail`, the archive
+/// is checked for the conflicts before anything is extracted.
+/// The tarballs are decompressed with the external `gzip`, `xz` or `zstd`.
+/// The zip archives aren't supported.
+pub fn extract(
+    archive: &Path,
+    format: ArchiveFormat,
+    destination: &Path,
+    on_conflict: OnConflict,
+) -> Fallible<Extracted> {
+    info!(
+        "Extracting {} to {}",
+        archive.to_string_lossy(),
+        destination.to_string_lossy()
+    );
+    if format == ArchiveFormat::Zip {
+        return Err(format_err!("extracting zip archives isn't supported"));
+    }
+    fs::create_dir_all(destination).context(format!(
+        "creating the directory {}",
+        destination.to_string_lossy()
+    ))?;
+
+    if on_conflict == OnConflict::Fail {
+        read_tar(archive, format, |reader| {
+            check_conflicts(reader, destination)
+        })?;
+    }
+    read_tar(archive, format, |reader| {
+        unpack_tar(reader, destination, on_conflict)
+    })
+}
+
+/// Passes the tarball to `f`, decompressing it if needed
+fn read_tar<T>(
+    archive: &Path,
+    format: ArchiveFormat,
+    f: impl FnOnce(&mut dyn io::Read) -> Fallible<T>,
+) -> Fallible<T> {
+    let mut file = fs::File::open(archive)
+        .context(format!("opening the archive {}", archive.to_string_lossy()))?;
+    let decompressor = match format {
+        ArchiveFormat::Tar => None,
+        ArchiveFormat::TarGz => Some("gzip"),
+        ArchiveFormat::TarXz => Some("xz"),
+        ArchiveFormat::TarZst => Some("zstd"),
+        ArchiveFormat::Zip => return Err(format_err!("zip archives aren't tarballs")),
+    };
+
+    match decompressor {
+        None => f(&mut file),
+        Some(program) => {
+            let mut child = process::Command::new(program)
+                .arg("-dc")
+                .stdin(file)
+                .stdout(process::Stdio::piped())
+                .spawn()
+                .context(format!("running {}", program))?;
+            let mut stdout = child.stdout.take().expect("no stdout of the child process");
+            let read = f(&mut stdout);
+            // The decompressor is waited for even if the reading has failed.
+            // The rest of the tarball may be left unread, so the pipe is closed first.
+            drop(stdout);
+            let status = child.wait()?;
+            let read = read?;
+            if !status.success() {
+                return Err(format_err!("{} has failed: {}", program, status));
+            }
+            Ok(read)
+        }
+    }
+}
+
+/// Checks that the path is relative and doesn't lead to the parent directory
+fn is_safe_path(path: &Path) -> bool {
+    path.components().all(|component| match component {
+        Component::Normal(_) | Component::CurDir => true,
+        _ => false,
+    })
+}
+
+/// Fails if any entry of the tarball conflicts with the existing files
+///
+/// The identical files and the existing directories aren't conflicts.
+fn check_conflicts<R: io::Read>(reader: R, destination: &Path) -> Fallible<()> {
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        let kind = entry.header().entry_type();
+        let supported = kind.is_file() || kind.is_dir() || kind.is_symlink() || kind.is_hard_link();
+        if !supported || !is_safe_path(&path) {
+            // Reported by unpack_tar
+            continue;
+        }
+
+        let target = destination.join(&path);
+        let meta = match fs::symlink_metadata(&target) {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        if meta.is_dir() && kind.is_dir() {
+            continue;
+        }
+        if kind.is_file() && meta.is_file() {
+            let size = entry.header().size()?;
+            if same_contents(&mut entry, size, &target)? {
+                continue;
+            }
+            return Err(format_err!(
+                "{} already exists, with different contents",
+                target.to_string_lossy()
+            ));
+        }
+        return Err(format_err!("{} already exists", target.to_string_lossy()));
+    }
+    Ok(())
+}
+
+fn unpack_tar<R: io::Read>(
+    reader: R,
+    destination: &Path,
+    on_conflict: OnConflict,
+) -> Fallible<Extracted> {
+    let mut extracted = Extracted::default();
+    let mut archive = tar::Archive::new(reader);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !is_safe_path(&path) {
+            return Err(format_err!(
+                "the archive contains an unsafe path: {}",
+                path.to_string_lossy()
+            ));
+        }
+
+        let kind = entry.header().entry_type();
+        if kind.is_symlink() || kind.is_hard_link() {
+            let link = entry.link_name()?.unwrap_or_default().into_owned();
+            // A symlink is resolved relative to its own directory
+            let resolved = if kind.is_symlink() {
+                path.parent().unwrap_or_else(|| Path::new("")).join(&link)
+            } else {
+                link.clone()
+            };
+            if !is_safe_path(&link) || !is_safe_path(&resolved) {
+                return Err(format_err!(
+                    "the archive contains a link leading outside of the directory: {} -> {}",
+                    path.to_string_lossy(),
+                    link.to_string_lossy()
+                ));
+            }
+        } else if !kind.is_file() && !kind.is_dir() {
+            warn!(
+                "Skipping {}, which isn't a file, a directory or a link",
+                path.to_string_lossy()
+            );
+            extracted.skipped += 1;
+            continue;
+        }
+
+        let target = destination.join(&path);
+        match fs::symlink_metadata(&target) {
+            // The existing directories are merged
+            Ok(ref meta) if meta.is_dir() && kind.is_dir() => {}
+            Ok(meta) => match on_conflict {
+                // The identical files, e.g. merged from different providers, aren't conflicts
+                OnConflict::Fail if kind.is_file() && meta.is_file() => {
+                    let size = entry.header().size()?;
+                    if !same_contents(&mut entry, size, &target)? {
+                        return Err(format_err!(
+                            "{} already exists, with different contents",
+                            target.to_string_lossy()
+                        ));
+                    }
+                    extracted.skipped += 1;
+                    continue;
+                }
+                OnConflict::Fail => {
+                    return Err(format_err!("{} already exists", target.to_string_lossy()))
+                }
+                OnConflict::Skip => {
+                    extracted.skipped += 1;
+                    continue;
+                }
+                OnConflict::Overwrite if meta.is_dir() => fs::remove_dir_all(&target)?,
+                OnConflict::Overwrite => fs::remove_file(&target)?,
+            },
+            Err(_) => {}
+        }
+
+        // unpack_in resolves the hard links relative to the destination, too
+        let unpacked = entry
+            .unpack_in(destination)
+            .context(format!("extracting {}", path.to_string_lossy()))?;
+        if !unpacked {
+            return Err(format_err!(
+                "the archive contains an unsafe path: {}",
+                path.to_string_lossy()
+            ));
+        }
+        if !kind.is_dir() {
+            extracted.files += 1;
+        }
+    }
+    Ok(extracted)
+}
+
+/// Checks if the contents of the entry are the same as of the existing file
+///
+/// The entry is consumed.
+fn same_contents<R: io::Read>(entry: &mut R, size: u64, path: &Path) -> io::Result<bool> {
+    let file = fs::File::open(path)?;
+    if file.metadata()?.len() != size {
+        return Ok(false);
+    }
+    let mut file = io::BufReader::new(file);
+    let mut expected = [0; 8192];
+    let mut existing = [0; 8192];
+    loop {
+        let len = entry.read(&mut expected)?;
+        if len == 0 {
+            return Ok(true);
+        }
+        file.read_exact(&mut existing[..len])?;
+        if expected[..len] != existing[..len] {
+            return Ok(false);
+        }
+    }
+}
+
+/// The archive with the sources
+pub enum SourceArchive {
+    /// an archive file, streamed from the disk
+    File(PathBuf),
+    /// an archive packed in memory
+    Packed(Vec<u8>),
+}
+
+/// Prepares the archive with the sources
+///
+/// `sources.path`, relative to `config_dir`, may be:
+/// * a ready-made archive, in any of the `ArchiveFormat`s
+/// * a directory, which is packed honoring the `.gitignore` files
+/// * a git repository, if `sources.revision` is set
+///
+/// Returns: the archive and its format
+pub fn pack_sources(
+    config_dir: &Path,
+    sources: &Sources,
+) -> Fallible<(SourceArchive, ArchiveFormat)> {
+    let path = config_dir.join(&sources.path);
+    match &sources.revision {
+        Some(revision) => Ok((
+            SourceArchive::Packed(git_archive(&path, revision)?),
+            ArchiveFormat::Tar,
+        )),
+        None if path.is_dir() => Ok((SourceArchive::Packed(tar_dir(&path)?), ArchiveFormat::Tar)),
+        None if path.is_file() => {
+            let format = ArchiveFormat::from_path(&path);
+            Ok((SourceArchive::File(path), format))
+        }
+        None => Err(format_err!(
+            "the source archive {} doesn't exist",
+            path.to_string_lossy()
+        )),
+    }
+}
+
+/// Packs the prebuilt binary, saved as `progname`, and its libraries, saved to `lib/`
+///
+/// All the files are marked as executable. The libraries must have distinct file names.
+pub fn pack_binary(config_dir: &Path, binary: &BinaryConfig, progname: &str) -> Fallible<Vec<u8>> {
+    let mut names = BTreeMap::new();
+    for lib in &binary.libraries {
+        let name = lib
+            .file_name()
+            .ok_or_else(|| format_err!("{} isn't a file", lib.to_string_lossy()))?;
+        if let Some(other) = names.insert(name, lib) {
+            return Err(format_err!(
+                "the libraries {} and {} have the same name",
+                other.to_string_lossy(),
+                lib.to_string_lossy()
+            ));
+        }
+    }
+
+    let mut builder = tar::Builder::new(vec![]);
+    let files = iter::once((binary.path.clone(), PathBuf::from(progname))).chain(
+        binary.libraries.iter().map(|lib| {
+            let name = lib.file_name().map(PathBuf::from).unwrap_or_default();
+            (lib.clone(), Path::new("lib").join(name))
+        }),
+    );
+
+    for (path, name) in files {
+        let path = config_dir.join(path);
+        let mut file =
+            fs::File::open(&path).context(format!("opening {}", path.to_string_lossy()))?;
+        let mut header = tar::Header::new_gnu();
+        header.set_metadata(&file.metadata()?);
+        header.set_mode(0o755);
+        builder
+            .append_data(&mut header, name, &mut file)
+            .context(format!("packing {}", path.to_string_lossy()))?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+/// Archives the given revision of the git repository
+fn git_archive(repo: &Path, revision: &str) -> Fallible<Vec<u8>> {
+    info!(
+        "Archiving revision {} of {}",
+        revision,
+        repo.to_string_lossy()
+    );
+    let output = process::Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(&["archive", "--format=tar", revision])
+        .output()
+        .context("running git archive")?;
+    if !output.status.success() {
+        return Err(format_err!(
+            "git archive has failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(output.stdout)
+}
+
+/// Packs the directory into a tarball, skipping the files ignored by `.gitignore`
+///
+/// The tarball depends only on the names and the contents of the files, so that
+/// the hash of the sources survives a fresh checkout or touching the files.
+fn tar_dir(dir: &Path) -> Fallible<Vec<u8>> {
+    info!("Packing the directory {}", dir.to_string_lossy());
+    let mut builder = tar::Builder::new(vec![]);
+    builder.follow_symlinks(false);
+    // The timestamps and the owners are left out
+    builder.mode(tar::HeaderMode::Deterministic);
+
+    // .gitignore is honored even outside of a git repository
+    let walk = WalkBuilder::new(dir)
+        .hidden(false)
+        .add_custom_ignore_filename(".gitignore")
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+    for entry in walk {
+        let entry = entry?;
+        // The directories are created implicitly, along with the files
+        if entry.file_type().map_or(true, |t| t.is_dir()) {
+            continue;
+        }
+        let name = entry.path().strip_prefix(dir)?;
+        builder
+            .append_path_with_name(entry.path(), name)
+            .context(format!("packing {}", entry.path().to_string_lossy()))?;
+    }
+    Ok(builder.into_inner()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    #[test]
+    fn test_archive_format() {
+        let format = |name: &str| ArchiveFormat::from_path(Path::new(name));
+        assert_eq!(format("input.tar"), ArchiveFormat::Tar);
+        assert_eq!(format("data/input.tar.gz"), ArchiveFormat::TarGz);
+        assert_eq!(format("input.TGZ"), ArchiveFormat::TarGz);
+        assert_eq!(format("input.tar.xz"), ArchiveFormat::TarXz);
+        assert_eq!(format("input.tar.zst"), ArchiveFormat::TarZst);
+        assert_eq!(format("prog.zip"), ArchiveFormat::Zip);
+        assert_eq!(format("checkpoint"), ArchiveFormat::Tar);
+
+        assert!(ArchiveFormat::TarZst
+            .extract_check_cmd()
+            .starts_with("for tool in tar zstd sha256sum; do command -v $tool"));
+        assert!(ArchiveFormat::Zip
+            .compress_check_cmd()
+            .contains("needed for the .zip archives"));
+    }
+
+    #[test]
+    fn test_pack_binary_duplicates() {
+        let binary = BinaryConfig {
+            path: "build/app".into(),
+            libraries: vec!["build/libsolver.so".into(), "deps/libsolver.so".into()],
+        };
+        // The names are checked before any file is read
+        let err = pack_binary(Path::new("."), &binary, "app").unwrap_err();
+        assert!(err.to_string().contains("have the same name"));
+    }
+
+    #[test]
+    fn test_extract() {
+        let dir = env::temp_dir().join(format!("gumpi-test-extract-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("out.tar");
+        let mut builder = tar::Builder::new(vec![]);
+        let mut append = |name: &str, data: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, data).unwrap();
+        };
+        append("result.txt", b"new");
+        append("logs/run.log", b"log");
+        fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+
+        let dest = dir.join("out");
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("result.txt"), "old").unwrap();
+        let run = |on_conflict| extract(&archive, ArchiveFormat::Tar, &dest, on_conflict);
+
+        assert!(run(OnConflict::Fail).is_err());
+        fs::write(dest.join("result.txt"), "new").unwrap();
+        // The identical files aren't conflicts
+        run(OnConflict::Fail).unwrap();
+        fs::write(dest.join("result.txt"), "old").unwrap();
+        assert_eq!(
+            run(OnConflict::Skip).unwrap(),
+            Extracted {
+                files: 0,
+                skipped: 2
+            }
+        );
+        assert_eq!(fs::read_to_string(dest.join("result.txt")).unwrap(), "old");
+        run(OnConflict::Overwrite).unwrap();
+        assert_eq!(fs::read_to_string(dest.join("result.txt")).unwrap(), "new");
+        assert_eq!(
+            fs::read_to_string(dest.join("logs/run.log")).unwrap(),
+            "log"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(is_safe_path(Path::new("out/./result.txt")));
+        assert!(!is_safe_path(Path::new("../etc/passwd")));
+        assert!(!is_safe_path(Path::new("/etc/passwd")));
+        assert!(!is_safe_path(Path::new("out/../../etc")));
+    }
+
+    #[test]
+    fn test_extract_conflict() {
+        let dir = env::temp_dir().join(format!("gumpi-test-conflict-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("out.tar");
+        let mut builder = tar::Builder::new(vec![]);
+        for (name, data) in &[("a.txt", b"a"), ("b.txt", b"b")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, name, &data[..]).unwrap();
+        }
+        fs::write(&archive, builder.into_inner().unwrap()).unwrap();
+
+        let dest = dir.join("out");
+        fs::create_dir_all(&dest).unwrap();
+        fs::write(dest.join("b.txt"), "old").unwrap();
+        assert!(extract(&archive, ArchiveFormat::Tar, &dest, OnConflict::Fail).is_err());
+        // Nothing is extracted before the conflicting entry
+        assert!(!dest.join("a.txt").exists());
+        assert_eq!(fs::read_to_string(dest.join("b.txt")).unwrap(), "old");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tar_dir() {
+        let dir = env::temp_dir().join(format!("gumpi-test-tar-dir-{}", process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("build")).unwrap();
+        fs::write(dir.join(".gitignore"), "build/\n*.o\n").unwrap();
+        fs::write(dir.join("Makefile"), "all:\n").unwrap();
+        fs::write(dir.join("src/main.c"), "int main() {}\n").unwrap();
+        fs::write(dir.join("src/main.o"), "").unwrap();
+        fs::write(dir.join("build/app"), "").unwrap();
+
+        let tarball = tar_dir(&dir).unwrap();
+        fs::write(dir.join("src/main.c"), "int main() {}\n").unwrap();
+        // Rewriting a file with the same contents doesn't change the tarball
+        assert_eq!(tar_dir(&dir).unwrap(), tarball);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let names: Vec<_> = tar::Archive::new(&tarball[..])
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                assert_eq!(entry.header().uid().unwrap(), 0);
+                entry.path().unwrap().into_owned()
+            })
+            .collect();
+        // The files are packed in the order of their paths
+        assert_eq!(
+            names,
+            vec![
+                Path::new(".gitignore"),
+                Path::new("Makefile"),
+                Path::new("src/main.c")
+            ]
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::{
+    format_error_chain, gumpi_async, is_interrupted,
+    jobconfig::{BatchOpt, JobConfig, JobQueue, QueuedJob, RunOpt},
+    session::mpi::SessionMPI,
+    timing,
+};
+use failure::{format_err, Fallible, ResultExt};
+use futures::{
+    future::{self, Either},
+    prelude::*,
+    stream,
+};
+use gu_client::r#async::HubConnection;
+use log::{error, info};
+use serde_derive::Serialize;
+use std::{
+    cell::Cell, collections::BTreeMap, fs, net::SocketAddr, path::PathBuf, rc::Rc, time::Instant,
+};
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum JobStatus {
+    Success,
+    Failed,
+    /// The job hasn't been started, because the batch has been interrupted
+    Skipped,
+}
+
+/// The outcome of a single job of the batch, as written to the results file
+#[derive(Debug, Serialize)]
+struct JobResult {
+    config: PathBuf,
+    numproc: usize,
+    status: JobStatus,
+    error: Option<String>,
+    /// wall time of the whole job, in seconds
+    wall_time: Option<f64>,
+    output: Option<PathBuf>,
+    /// the SHA-256 digests of the transferred archives
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    checksums: Checksums,
+}
+
+/// The SHA-256 digests of the transferred archives, by the description of the archive
+type Checksums = BTreeMap<String, String>;
+
+/// Runs all the jobs from the manifest, sharing a single hub connection
+///
+/// A failure of a single job doesn't stop the batch. The remaining jobs
+/// are skipped only if the execution is interrupted.
+pub fn run_batch(opt: BatchOpt) -> Fallible<impl Future<Item = (), Error = failure::Error>> {
+    if opt.concurrency == 0 {
+        return Err(format_err!("The concurrency must be at least 1"));
+    }
+    let queue = JobQueue::from_file(&opt.manifest).context("reading the job manifest")?;
+    let manifest_dir = opt
+        .manifest
+        .parent()
+        .expect("Invalid manifest path")
+        .to_owned();
+    let hub_conn = SessionMPI::connect(opt.hub)?;
+    let hub = opt.hub;
+    let noclean = opt.noclean;
+    let results_path = opt.results;
+    let njobs = queue.jobs.len();
+    let interrupted = Rc::new(Cell::new(false));
+
+    let future = stream::iter_ok(queue.jobs.into_iter().enumerate())
+        .map(move |(idx, job)| {
+            let config_path = manifest_dir.join(&job.config);
+            let numproc = job.numproc;
+            if interrupted.get() {
+                info!("Skipping job {}/{}", idx + 1, njobs);
+                return Either::A(future::ok(JobResult {
+                    config: config_path,
+                    numproc,
+                    status: JobStatus::Skipped,
+                    error: None,
+                    wall_time: None,
+                    output: None,
+                    checksums: BTreeMap::new(),
+                }));
+            }
+
+            info!(
+                "Starting job {}/{}: {}",
+                idx + 1,
+                njobs,
+                config_path.to_string_lossy()
+            );
+            let start = Instant::now();
+            let job_future = match run_job(&hub_conn, hub, noclean, config_path.clone(), job) {
+                Ok(fut) => Either::A(fut),
+                Err(e) => Either::B(future::err(e)),
+            };
+            let interrupted = Rc::clone(&interrupted);
+
+            Either::B(job_future.then(move |res| -> Fallible<_> {
+                let wall_time = Some(timing::as_secs(start.elapsed()));
+                let result = match res {
+                    Ok((output, checksums)) => {
+                        info!("Job {}/{} finished", idx + 1, njobs);
+                        JobResult {
+                            config: config_path,
+                            numproc,
+                            status: JobStatus::Success,
+                            error: None,
+                            wall_time,
+                            output,
+                            checksums,
+                        }
+                    }
+                    Err(e) => {
+                        if is_interrupted(&e) {
+                            interrupted.set(true);
+                        }
+                        let msg = format_error_chain(&e);
+                        error!("Job {}/{} failed: {}", idx + 1, njobs, msg);
+                        JobResult {
+                            config: config_path,
+                            numproc,
+                            status: JobStatus::Failed,
+                            error: Some(msg),
+                            wall_time,
+                            output: None,
+                            checksums: BTreeMap::new(),
+                        }
+                    }
+                };
+                Ok(result)
+            }))
+        })
+        .buffered(opt.concurrency)
+        .collect()
+        .and_then(move |results| -> Fallible<()> {
+            let json = serde_json::to_string_pretty(&results)?;
+            fs::write(&results_path, json).context(format!(
+                "writing the results to {}",
+                results_path.to_string_lossy()
+            ))?;
+
+            let succeeded = results
+                .iter()
+                .filter(|r| r.status == JobStatus::Success)
+                .count();
+            println!("{} of {} jobs succeeded", succeeded, njobs);
+            if succeeded < njobs {
+                Err(format_err!("{} jobs did not succeed", njobs - succeeded))
+            } else {
+                Ok(())
+            }
+        });
+    Ok(future)
+}
+
+/// Returns: the path to the retrieved outputs, if the job has any,
+/// and the digests of the transferred archives
+fn run_job(
+    hub_conn: &HubConnection,
+    hub: SocketAddr,
+    noclean: bool,
+    config_path: PathBuf,
+    job: QueuedJob,
+) -> Fallible<impl Future<Item = (Option<PathBuf>, Checksums), Error = failure::Error>> {
+    let config = JobConfig::from_file(&config_path).context("reading job config")?;
+    let output = config.output.as_ref().map(|outs| outs.target.clone());
+    let opt = RunOpt {
+        numproc: job.numproc,
+        hub,
+        jobconfig: config_path,
+        providers: job.providers,
+        noclean,
+        repeat: 1,
+        warmup: 0,
+        timing_summary: None,
+        keep_outputs: None,
+        resume: false,
+        rank_logs: None,
+        phase_report: None,
+        build_logs: None,
+    };
+    let future = gumpi_async(hub_conn, opt, config)?.map(move |checksums| (output, checksums));
+    Ok(future)
+}
@@ -0,0 +1,53 @@
+use crate::{error::Error, session::mpi::CompilationInfo};
+use failure::{Fallible, ResultExt};
+use gu_client::NodeId;
+use log::{info, warn};
+use std::{fs, path::Path};
+
+/// Joins the outputs of the build steps
+pub fn join(logs: &[String]) -> String {
+    logs.join("\n------------------\n")
+}
+
+/// Writes the compilation logs of the node to `<dir>/<node>.log`
+fn save(dir: &Path, node: &NodeId, logs: &[String]) -> Fallible<()> {
+    fs::create_dir_all(dir).context(format!(
+        "creating the log directory {}",
+        dir.to_string_lossy()
+    ))?;
+    let path = dir.join(format!("{}.log", node.to_string()));
+    fs::write(&path, join(logs)).context(format!("writing the log {}", path.to_string_lossy()))?;
+    Ok(())
+}
+
+/// Saves the compilation logs of all the nodes
+pub fn save_all(infos: &[CompilationInfo], dir: &Path) -> Fallible<()> {
+    for comp in infos {
+        save(dir, &comp.node, &comp.logs)?;
+    }
+    info!("Compilation logs written to {}", dir.to_string_lossy());
+    Ok(())
+}
+
+/// Saves the compilation logs of the failed nodes, if the error contains them
+pub fn save_from_error(e: &failure::Error, dir: &Path) {
+    let failures = e
+        .iter_chain()
+        .filter_map(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::CompilationError(failures)) => Some(failures),
+            _ => None,
+        })
+        .next();
+    if let Some(failures) = failures {
+        for failure in failures {
+            if let Err(e) = save(dir, &failure.node, &failure.logs) {
+                warn!("Couldn't save the compilation logs: {}", e);
+                return;
+            }
+        }
+        info!(
+            "Compilation logs of the failed nodes written to {}",
+            dir.to_string_lossy()
+        );
+    }
+}
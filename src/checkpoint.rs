@@ -0,0 +1,145 @@
+use crate::{archive::ArchiveFormat, jobconfig::CheckpointConfig, session::mpi::SessionMPI};
+use failure::{Fallible, ResultExt};
+use failure_ext::FutureExt;
+use futures::{
+    future::{self, Either, Loop},
+    prelude::*,
+};
+use log::{info, warn};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+use tokio::timer::Delay;
+
+/// The checkpoint is downloaded to a temporary file first, so that an
+/// interrupted download never overwrites the previous checkpoint
+fn partial_path(target: &Path) -> PathBuf {
+    let mut path = target.as_os_str().to_owned();
+    path.push(".part");
+    PathBuf::from(path)
+}
+
+/// Returns: the format of the checkpoint archive, implied by the name of the target
+///
+/// The same format is used for pulling and pushing the checkpoints.
+fn archive_format(cfg: &CheckpointConfig) -> ArchiveFormat {
+    ArchiveFormat::from_path(&cfg.target)
+}
+
+/// Downloads the checkpoint directory from the root provider
+pub fn pull(
+    session: &SessionMPI,
+    cfg: &CheckpointConfig,
+) -> impl Future<Item = (), Error = failure::Error> {
+    let partial = partial_path(&cfg.target);
+    let target = cfg.target.clone();
+
+    session
+        .retrieve_dir(&cfg.path, partial.clone(), archive_format(cfg))
+        .and_then(move |()| -> Fallible<()> {
+            fs::rename(&partial, &target).context(format!(
+                "saving the checkpoint to {}",
+                target.to_string_lossy()
+            ))?;
+            info!("Saved the checkpoint to {}", target.to_string_lossy());
+            Ok(())
+        })
+}
+
+/// Like `pull`, but only warns about the failures
+pub fn pull_best_effort(
+    session: &SessionMPI,
+    cfg: &CheckpointConfig,
+) -> impl Future<Item = (), Error = failure::Error> {
+    pull(session, cfg).then(|res| {
+        if let Err(e) = res {
+            warn!("Couldn't pull the checkpoint: {}", e);
+        }
+        Ok::<_, failure::Error>(())
+    })
+}
+
+/// Uploads the latest checkpoint to the checkpoint directory on the active providers
+///
+/// If there's no checkpoint saved yet, nothing is uploaded.
+pub fn push(
+    session: &SessionMPI,
+    cfg: &CheckpointConfig,
+) -> impl Future<Item = (), Error = failure::Error> {
+    if !cfg.target.is_file() {
+        warn!(
+            "No checkpoint found at {}, starting from scratch",
+            cfg.target.to_string_lossy()
+        );
+        return Either::A(future::ok(()));
+    }
+
+    info!("Uploading the checkpoint {}", cfg.target.to_string_lossy());
+    let path = cfg.path.to_string_lossy().into_owned();
+    Either::B(
+        session
+            .upload_archive(cfg.target.clone(), archive_format(cfg), path)
+            .context("uploading the checkpoint")
+            .map(|_| ()),
+    )
+}
+
+/// Runs `exec`, pulling the checkpoints periodically until it finishes
+pub fn exec_with_checkpoints<F>(
+    exec: F,
+    session: Rc<SessionMPI>,
+    cfg: Option<CheckpointConfig>,
+) -> impl Future<Item = F::Item, Error = failure::Error>
+where
+    F: Future<Error = failure::Error>,
+{
+    let cfg = match cfg {
+        Some(cfg) => cfg,
+        None => return Either::A(exec),
+    };
+
+    let interval = Duration::from_secs(cfg.interval);
+    let pulls = future::loop_fn((), move |()| {
+        let session = Rc::clone(&session);
+        let cfg = cfg.clone();
+        Delay::new(Instant::now() + interval)
+            .from_err::<failure::Error>()
+            .and_then(move |()| pull_best_effort(&session, &cfg))
+            .map(|()| Loop::<(), ()>::Continue(()))
+    });
+
+    Either::B(exec.select2(pulls).then(|res| match res {
+        Ok(Either::A((item, _))) => Either::A(future::ok(item)),
+        Err(Either::A((e, _))) => Either::A(future::err(e)),
+        // The pulls stop only if the timer fails, the job keeps running anyway
+        Ok(Either::B(((), exec))) => Either::B(exec),
+        Err(Either::B((e, exec))) => {
+            warn!("Stopped pulling the checkpoints: {}", e);
+            Either::B(exec)
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive_format() {
+        let cfg = CheckpointConfig {
+            path: "/output/checkpoint".into(),
+            target: "ckpt.tar.gz".into(),
+            interval: 600,
+        };
+        assert_eq!(archive_format(&cfg), ArchiveFormat::TarGz);
+        // The name of the partial download doesn't imply the format
+        assert_eq!(partial_path(&cfg.target), Path::new("ckpt.tar.gz.part"));
+        assert_eq!(
+            ArchiveFormat::from_path(&partial_path(&cfg.target)),
+            ArchiveFormat::Tar
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use crate::{
+    check_cpus, check_input, cleanup, job_libdir,
+    jobconfig::{JobConfig, JobRefOpt, OutputConfig, StatusOpt, SubmitOpt},
+    prepare_session,
+    session::mpi::{DetachedStatus, SessionMPI, SessionState},
+    timing::SharedPhaseReport,
+};
+use failure::{format_err, Fallible, ResultExt};
+use failure_ext::FutureExt;
+use futures::{
+    future::{self, Either, Loop},
+    prelude::*,
+};
+use gu_client::r#async::HubConnection;
+use log::{debug, info};
+use serde_derive::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::{self, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use tokio::timer::Delay;
+use tokio_ctrlc_error::AsyncCtrlc;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The record of a job running in the background, saved in the state directory
+#[derive(Debug, Serialize, Deserialize)]
+struct JobState {
+    id: String,
+    hub: SocketAddr,
+    session: SessionState,
+    /// The PID of the process group running mpirun on the root provider
+    pid: u32,
+    /// The output configuration, with absolute target and extraction paths
+    output: Option<OutputConfig>,
+    noclean: bool,
+    /// The SHA-256 digests of the archives transferred before the execution
+    #[serde(default)]
+    checksums: BTreeMap<String, String>,
+}
+
+impl JobState {
+    fn path(state_dir: &Path, id: &str) -> PathBuf {
+        state_dir.join(format!("{}.json", id))
+    }
+
+    fn load(state_dir: &Path, id: &str) -> Fallible<Self> {
+        let path = Self::path(state_dir, id);
+        let json = fs::read_to_string(&path)
+            .context(format!("reading the job record {}", path.to_string_lossy()))?;
+        let state = serde_json::from_str(&json).context("invalid job record")?;
+        Ok(state)
+    }
+
+    fn save(&self, state_dir: &Path) -> Fallible<()> {
+        let path = Self::path(state_dir, &self.id);
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(&path, json)
+            .context(format!("writing the job record {}", path.to_string_lossy()))?;
+        Ok(())
+    }
+
+    fn remove(state_dir: &Path, id: &str) -> Fallible<()> {
+        let path = Self::path(state_dir, id);
+        fs::remove_file(&path).context(format!(
+            "removing the job record {}",
+            path.to_string_lossy()
+        ))?;
+        Ok(())
+    }
+}
+
+fn state_dir(dir: Option<PathBuf>) -> PathBuf {
+    dir.unwrap_or_else(|| {
+        env::var_os("HOME")
+            .map(PathBuf::from)
+            .unwrap_or_default()
+            .join(".gumpi")
+            .join("jobs")
+    })
+}
+
+fn new_job_id() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    format!("{}-{}", secs, std::process::id())
+}
+
+/// Prepares the session and starts the job, without waiting for it to finish
+///
+/// The session is closed only if the job couldn't be started. Otherwise,
+/// it's left for `attach` or `cancel`.
+pub fn submit(
+    hub_conn: &HubConnection,
+    opt: SubmitOpt,
+    config: JobConfig,
+) -> Fallible<impl Future<Item = (), Error = failure::Error>> {
+    let cpus_requested = opt.numproc;
+    let prov_filter = if opt.providers.is_empty() {
+        None
+    } else {
+        debug!("Chosen providers: {:?}", opt.providers);
+        Some(opt.providers)
+    };
+    let jobconfig_dir = opt
+        .jobconfig
+        .parent()
+        .expect("Invalid jobconfig path")
+        .to_owned();
+    let noclean = opt.noclean;
+    let hub = opt.hub;
+    check_input(&config, &jobconfig_dir)?;
+
+    let state_dir = state_dir(opt.state_dir);
+    fs::create_dir_all(&state_dir).context(format!(
+        "creating the state directory {}",
+        state_dir.to_string_lossy()
+    ))?;
+
+    // The job may be collected from a different working directory
+    let cwd = env::current_dir().context("getting the working directory")?;
+    let output = config.output.clone().map(|mut outs| {
+        outs.target = cwd.join(outs.target);
+        outs.extract = outs.extract.map(|dir| cwd.join(dir));
+        outs
+    });
+
+    let future = SessionMPI::init(hub_conn, prov_filter)
+        .ctrlc_as_error()
+        .context("initializing session")
+        .and_then(move |session| {
+            let session = Rc::new(session);
+            let mut session_clone = Rc::clone(&session);
+
+            if let Err(e) = check_cpus(&session, cpus_requested) {
+                return Either::A(future::err(e));
+            }
+
+            // The execution isn't waited for, so there's no phase report to show
+            let report = SharedPhaseReport::default();
+            Either::B(
+                prepare_session(&session, &config, jobconfig_dir, None, &report)
+                    .and_then(move |bindir| {
+                        let libdir = job_libdir(&config);
+                        session
+                            .exec_detached(
+                                cpus_requested,
+                                config.progname,
+                                config.args,
+                                config.mpiargs.unwrap_or_default(),
+                                bindir,
+                                libdir,
+                            )
+                            .context("starting the program")
+                            .map(move |pid| (pid, session))
+                    })
+                    .and_then(move |(pid, session)| -> Fallible<()> {
+                        let state = JobState {
+                            id: new_job_id(),
+                            hub,
+                            session: session.state(),
+                            pid,
+                            output,
+                            noclean,
+                            checksums: session.digests(),
+                        };
+                        state.save(&state_dir)?;
+                        println!("Submitted job {}", state.id);
+                        Ok(())
+                    })
+                    .ctrlc_as_error()
+                    .or_else(move |e| {
+                        // The job hasn't been started, so there's no point in keeping the session
+                        cleanup(&mut session_clone, noclean).then(|_| Err(e))
+                    }),
+            )
+        });
+    Ok(future)
+}
+
+pub fn status(opt: StatusOpt) -> Fallible<impl Future<Item = (), Error = failure::Error>> {
+    let state_dir = state_dir(opt.state_dir);
+    let id = match opt.job_id {
+        Some(id) => id,
+        None => {
+            list_jobs(&state_dir)?;
+            return Ok(Either::A(future::ok(())));
+        }
+    };
+
+    let state = JobState::load(&state_dir, &id)?;
+    let hub_conn = SessionMPI::connect(state.hub)?;
+    let session = SessionMPI::restore(&hub_conn, state.session);
+    let future = session
+        .poll_detached(state.pid, None)
+        .context("checking the job status")
+        .map(move |(status, _)| println!("Job {}: {}", id, status));
+    Ok(Either::B(future))
+}
+
+fn list_jobs(state_dir: &Path) -> Fallible<()> {
+    if !state_dir.is_dir() {
+        println!("No jobs saved");
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(state_dir)? {
+        let path = entry?.path();
+        if path.extension().map_or(false, |ext| ext == "json") {
+            if let Some(id) = path.file_stem() {
+                println!("{}", id.to_string_lossy());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Follows the output of the job until it finishes, then collects it
+///
+/// Collecting the job means retrieving its outputs and closing the session.
+pub fn attach(opt: JobRefOpt) -> Fallible<impl Future<Item = (), Error = failure::Error>> {
+    let state_dir = state_dir(opt.state_dir);
+    let id = opt.job_id;
+    let state = JobState::load(&state_dir, &id)?;
+    let hub_conn = SessionMPI::connect(state.hub)?;
+    let session = Rc::new(SessionMPI::restore(&hub_conn, state.session));
+    let poll_session = Rc::clone(&session);
+    let pid = state.pid;
+    let output_cfg = state.output;
+    let noclean = state.noclean;
+    let mut checksums = state.checksums;
+
+    let follow = future::loop_fn(0, move |offset| {
+        poll_session
+            .poll_detached(pid, Some(offset))
+            .context("following the job output")
+            .and_then(move |(status, log)| -> Fallible<_> {
+                // The raw bytes are passed through, so that the characters split
+                // between two polls are put together by the terminal
+                let mut stdout = io::stdout();
+                stdout.write_all(&log)?;
+                stdout.flush()?;
+                let offset = offset + log.len() as u64;
+                if status == DetachedStatus::Running {
+                    let delay = Delay::new(Instant::now() + POLL_INTERVAL)
+                        .from_err::<failure::Error>()
+                        .map(move |()| Loop::Continue(offset));
+                    Ok(Either::A(delay))
+                } else {
+                    Ok(Either::B(future::ok(Loop::Break(status))))
+                }
+            })
+            .flatten()
+    });
+
+    let future = follow.ctrlc_as_error().and_then(move |status| {
+        info!("Job {}: {}", id, status);
+        let retrieve = if let Some(outs) = output_cfg {
+            Either::A(session.retrieve_output(&outs).context("retrieving output"))
+        } else {
+            Either::B(future::ok(()))
+        };
+
+        retrieve
+            .and_then(move |()| {
+                checksums.extend(session.digests());
+                if !checksums.is_empty() {
+                    println!("Checksums (SHA-256):");
+                    for (name, digest) in &checksums {
+                        println!("  {} {}", digest, name);
+                    }
+                }
+                close_session(&session, noclean)
+            })
+            .and_then(move |()| JobState::remove(&state_dir, &id))
+            .and_then(move |()| match status {
+                DetachedStatus::Exited(0) => Ok(()),
+                DetachedStatus::Exited(code) => {
+                    Err(format_err!("The job exited with code {}", code))
+                }
+                _ => Err(format_err!("The job is {}", status)),
+            })
+    });
+    Ok(future)
+}
+
+fn close_session(
+    session: &SessionMPI,
+    noclean: bool,
+) -> impl Future<Item = (), Error = failure::Error> {
+    if noclean {
+        Either::A(future::ok(()))
+    } else {
+        Either::B(session.close().context("closing the session"))
+    }
+}
+
+/// Terminates the job and closes its session
+pub fn cancel(opt: JobRefOpt) -> Fallible<impl Future<Item = (), Error = failure::Error>> {
+    let state_dir = state_dir(opt.state_dir);
+    let id = opt.job_id;
+    let state = JobState::load(&state_dir, &id)?;
+    let hub_conn = SessionMPI::connect(state.hub)?;
+    let session = SessionMPI::restore(&hub_conn, state.session);
+    let noclean = state.noclean;
+
+    let future = session
+        .cancel_detached(state.pid)
+        .context("cancelling the job")
+        .and_then(move |()| close_session(&session, noclean))
+        .and_then(move |()| -> Fallible<()> {
+            JobState::remove(&state_dir, &id)?;
+            println!("Cancelled job {}", id);
+            Ok(())
+        });
+    Ok(future)
+}
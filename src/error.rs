@@ -1,10 +1,11 @@
 use failure::Fail;
+use gu_client::NodeId;
 use std::fmt;
 
 #[derive(Debug, Fail)]
 pub enum Error {
     ExecutionError(String),
-    CompilationError(Vec<String>),
+    CompilationError(Vec<CompilationFailure>),
     KeyDeploymentError(Vec<String>),
 }
 
@@ -12,11 +13,45 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         match self {
             Error::ExecutionError(e) => writeln!(f, "execution error:\n{}", e),
-            Error::CompilationError(logs) => {
-                let joined = logs.join("\n----------\n");
-                writeln!(f, "compilation error:\n{}", joined)
+            Error::CompilationError(failures) => {
+                writeln!(f, "compilation error on {} node(s)", failures.len())?;
+                for failure in failures {
+                    write!(f, "{}", failure)?;
+                }
+                Ok(())
             }
             Error::KeyDeploymentError(e) => writeln!(f, "error deploying keys:\n{:?}", e),
         }
     }
 }
+
+/// The number of the last lines of the output shown in the failure summary
+const TAIL_LINES: usize = 20;
+
+/// The failure of the build on a single node
+#[derive(Clone, Debug)]
+pub struct CompilationFailure {
+    pub node: NodeId,
+    /// the description of the failing build step
+    pub step: String,
+    /// the outputs of the build steps, up to the failing one
+    pub logs: Vec<String>,
+}
+
+impl fmt::Display for CompilationFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        writeln!(
+            f,
+            "node {}: `{}` has failed, the last lines of the output:",
+            self.node.to_string(),
+            self.step
+        )?;
+        let output = self.logs.last().map_or("", String::as_str);
+        let lines: Vec<_> = output.lines().collect();
+        let tail = &lines[lines.len().saturating_sub(TAIL_LINES)..];
+        for line in tail {
+            writeln!(f, "    {}", line)?;
+        }
+        Ok(())
+    }
+}
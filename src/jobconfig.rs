@@ -1,7 +1,11 @@
-use failure::{Fallible, ResultExt};
+use crate::archive::{ArchiveFormat, OnConflict};
+use failure::{format_err, Fallible, ResultExt};
 use gu_client::NodeId;
+use serde::{de::DeserializeOwned, Deserializer};
 use serde_derive::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
+    ffi::OsString,
     fs::File,
     io::Read,
     net::SocketAddr,
@@ -13,12 +17,91 @@ use structopt::StructOpt;
 pub enum BuildType {
     Make,
     CMake,
+    /// `./configure CC=mpicc CXX=mpicxx`, then `make`
+    Autotools,
+    /// `meson` with the MPI compiler wrappers, then `ninja`
+    Meson,
+    /// `cargo build --release`, e.g. for the applications using rsmpi
+    Cargo,
+    /// a custom build script, see `Sources::script`
+    Script,
+}
+
+/// Which providers compile the sources
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildOn {
+    /// every provider builds the application on its own
+    Node,
+    /// a single provider of every platform builds the application,
+    /// the rest of the providers of the same platform get a copy of its build
+    Platform,
+}
+
+impl Default for BuildOn {
+    fn default() -> Self {
+        BuildOn::Node
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Sources {
+    /// a tarball, a directory or a git repository, see `archive::pack_sources`
     pub path: PathBuf,
+    /// the revision to archive, if `path` is a git repository
+    pub revision: Option<String>,
     pub mode: BuildType,
+    /// the build script, relative to the root of the sources,
+    /// required for the `Script` build type
+    pub script: Option<PathBuf>,
+    /// additional definitions passed to CMake or Meson as `-Dname=value`
+    #[serde(default)]
+    pub defines: BTreeMap<String, String>,
+    /// the CMake or Meson build type, defaults to the release build
+    pub build_type: Option<String>,
+    /// the targets to build instead of the default one
+    #[serde(default)]
+    pub targets: Vec<String>,
+    /// the number of parallel build jobs, defaults to the number of cores of the provider
+    /// for Meson and Cargo, make runs sequentially unless it's set
+    pub jobs: Option<usize>,
+    /// the environment variables set for the build commands
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// the out-of-tree build directory, relative to the root of the sources,
+    /// not supported by the `Make` and `Script` build types
+    pub build_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub build_on: BuildOn,
+}
+
+/// What to do if the providers have different MPI or compiler versions,
+/// or different architectures
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ToolchainCheck {
+    /// don't probe the providers
+    Off,
+    /// log the differences and run the job anyway
+    Warn,
+    /// fail the job
+    Refuse,
+}
+
+impl Default for ToolchainCheck {
+    fn default() -> Self {
+        ToolchainCheck::Warn
+    }
+}
+
+/// A prebuilt binary, uploaded instead of building the sources
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BinaryConfig {
+    /// the executable, relative to the job configuration file
+    pub path: PathBuf,
+    /// the shared libraries needed by the executable
+    #[serde(default)]
+    pub libraries: Vec<PathBuf>,
 }
 
 fn default_output_location() -> PathBuf {
@@ -30,11 +113,160 @@ pub struct OutputConfig {
     #[serde(default = "default_output_location")]
     pub source: PathBuf,
     pub target: PathBuf,
+    /// the format of the output archive, guessed from `target` if not given
+    pub format: Option<ArchiveFormat>,
+    /// the maximum size of the output archive, in bytes
+    pub max_size: Option<u64>,
+    /// the files to retrieve, by default all of them
+    #[serde(flatten)]
+    pub filter: FileFilter,
+    /// the directory the output archive is extracted to, if any
+    pub extract: Option<PathBuf>,
+    /// what to do with the files which already exist in `extract`
+    #[serde(default)]
+    pub on_conflict: OnConflict,
+    #[serde(default)]
+    pub scope: OutputScope,
+    /// extract the archives of all the providers into `extract` itself,
+    /// rather than into per-node subdirectories
+    #[serde(default)]
+    pub merge: bool,
+}
+
+/// The glob patterns selecting the files, matched against their paths relative
+/// to the directory, e.g. `results/*.csv`
+///
+/// `*` matches `/` as well, so `*.csv` selects the files in all the subdirectories.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct FileFilter {
+    /// the files to select, all of them if empty
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// the files to skip, even if included
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+impl FileFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+}
+
+/// The providers the output is retrieved from
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputScope {
+    /// only the root provider, which runs mpirun
+    Root,
+    /// all the providers, into per-node files in the `target` directory
+    All,
+}
+
+impl Default for OutputScope {
+    fn default() -> Self {
+        OutputScope::Root
+    }
+}
+
+impl OutputConfig {
+    /// Returns: the format given in the config or implied by the name of the target file
+    pub fn format(&self) -> ArchiveFormat {
+        self.format
+            .unwrap_or_else(|| ArchiveFormat::from_path(&self.target))
+    }
+}
+
+fn default_input_location() -> PathBuf {
+    PathBuf::from("/input")
+}
+
+/// The providers an input archive is uploaded to
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InputScope {
+    All,
+    /// only the root provider, which runs mpirun
+    Root,
+}
+
+impl Default for InputScope {
+    fn default() -> Self {
+        InputScope::All
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct InputConfig {
+    /// the archive, relative to the job configuration file
     pub source: PathBuf,
+    /// the absolute path of the directory the archive is unpacked to
+    #[serde(default = "default_input_location")]
+    pub destination: PathBuf,
+    #[serde(default)]
+    pub scope: InputScope,
+}
+
+/// Accepts either a single `[input]` table or an array of `[[input]]` tables
+fn deserialize_inputs<'de, D>(deserializer: D) -> Result<Vec<InputConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Inputs {
+        One(InputConfig),
+        Many(Vec<InputConfig>),
+    }
+
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        Inputs::One(input) => vec![input],
+        Inputs::Many(inputs) => inputs,
+    })
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CheckpointConfig {
+    /// the checkpoint directory on the provider
+    pub path: PathBuf,
+    /// the file on the client the latest checkpoint is saved to
+    pub target: PathBuf,
+    /// the number of seconds between pulling the checkpoints,
+    /// at least `MIN_CHECKPOINT_INTERVAL`
+    pub interval: u64,
+}
+
+/// The minimum number of seconds between pulling the checkpoints,
+/// so that packing the checkpoint directory doesn't slow down the job
+const MIN_CHECKPOINT_INTERVAL: u64 = 10;
+
+/// The kinds of failures of the program execution
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureClass {
+    /// mpirun couldn't start or lost the daemon on one of the nodes
+    Node,
+    /// the MPI processes couldn't communicate with each other
+    Network,
+    /// the application itself has failed
+    Application,
+    /// the communication with the hub or the root provider has failed
+    Hub,
+}
+
+fn default_retry_on() -> Vec<FailureClass> {
+    vec![FailureClass::Node, FailureClass::Network]
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RetryConfig {
+    /// the maximum number of attempts, including the first one
+    pub max_attempts: usize,
+    #[serde(default = "default_retry_on")]
+    pub retry_on: Vec<FailureClass>,
+    /// whether to stop using the providers involved in the failure
+    #[serde(default)]
+    pub exclude_failed: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -43,27 +275,164 @@ pub struct JobConfig {
     pub args: Vec<String>,
     pub mpiargs: Option<Vec<String>>,
     pub sources: Option<Sources>,
+    pub binary: Option<BinaryConfig>,
     pub output: Option<OutputConfig>,
-    pub input: Option<InputConfig>,
+    #[serde(default, deserialize_with = "deserialize_inputs")]
+    pub input: Vec<InputConfig>,
+    pub retry: Option<RetryConfig>,
+    pub checkpoint: Option<CheckpointConfig>,
+    #[serde(default)]
+    pub toolchain_check: ToolchainCheck,
 }
 
 impl JobConfig {
     pub fn from_file(path: &Path) -> Fallible<Self> {
-        let mut file = File::open(path).context(format!(
-            "Failed to open configuration file: {}",
-            path.display()
-        ))?;
-        let mut cfgstr = String::new();
-        file.read_to_string(&mut cfgstr)
-            .context("Failed to read the configuration file")?;
-        let config: Self = toml::from_str(&cfgstr).context("Failed to load configuration")?;
+        let config: Self = read_toml(path)?;
+        config.validate()?;
         Ok(config)
     }
+
+    fn validate(&self) -> Fallible<()> {
+        if self.sources.is_some() && self.binary.is_some() {
+            return Err(format_err!(
+                "The [sources] and [binary] sections are mutually exclusive"
+            ));
+        }
+        if let Some(output) = &self.output {
+            if output.extract.is_some() && output.format() == ArchiveFormat::Zip {
+                return Err(format_err!("Extracting zip archives isn't supported"));
+            }
+            let patterns = output.filter.include.iter().chain(&output.filter.exclude);
+            if let Some(pattern) = patterns.into_iter().find(|p| Path::new(p).is_absolute()) {
+                return Err(format_err!(
+                    "The output pattern {} isn't relative to the output directory",
+                    pattern
+                ));
+            }
+            if output.merge && (output.scope != OutputScope::All || output.extract.is_none()) {
+                return Err(format_err!(
+                    "Merging the output requires scope = \"all\" and the extraction directory"
+                ));
+            }
+        }
+        for input in &self.input {
+            if !input.destination.is_absolute() {
+                return Err(format_err!(
+                    "The input destination {} isn't an absolute path",
+                    input.destination.to_string_lossy()
+                ));
+            }
+        }
+        if let Some(checkpoint) = &self.checkpoint {
+            if checkpoint.interval < MIN_CHECKPOINT_INTERVAL {
+                return Err(format_err!(
+                    "The checkpoint interval has to be at least {} seconds",
+                    MIN_CHECKPOINT_INTERVAL
+                ));
+            }
+        }
+        if let Some(sources) = &self.sources {
+            if sources.mode == BuildType::Script && sources.script.is_none() {
+                return Err(format_err!(
+                    "The Script build type requires the build script to be set in [sources]"
+                ));
+            }
+            let in_tree = sources.mode == BuildType::Make || sources.mode == BuildType::Script;
+            if in_tree && sources.build_dir.is_some() {
+                return Err(format_err!(
+                    "The {:?} build type doesn't support the build directory",
+                    sources.mode
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single entry of the job queue
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct QueuedJob {
+    /// path to the job config, relative to the manifest file
+    pub config: PathBuf,
+    pub numproc: usize,
+    #[serde(default)]
+    pub providers: Vec<NodeId>,
+}
+
+/// The manifest describing a batch of jobs
+#[derive(Debug, Serialize, Deserialize)]
+pub struct JobQueue {
+    #[serde(rename = "job")]
+    pub jobs: Vec<QueuedJob>,
+}
+
+impl JobQueue {
+    pub fn from_file(path: &Path) -> Fallible<Self> {
+        read_toml(path)
+    }
+}
+
+fn read_toml<T: DeserializeOwned>(path: &Path) -> Fallible<T> {
+    let mut file = File::open(path).context(format!(
+        "Failed to open configuration file: {}",
+        path.display()
+    ))?;
+    let mut cfgstr = String::new();
+    file.read_to_string(&mut cfgstr)
+        .context("Failed to read the configuration file")?;
+    let config: T = toml::from_str(&cfgstr).context("Failed to load configuration")?;
+    Ok(config)
 }
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "gumpi", about = "MPI on Golem Unlimited")]
-pub struct Opt {
+pub enum Opt {
+    #[structopt(name = "run", about = "run a single job")]
+    Run(RunOpt),
+    #[structopt(name = "batch", about = "run the jobs listed in a manifest file")]
+    Batch(BatchOpt),
+    #[structopt(name = "submit", about = "start a job in the background")]
+    Submit(SubmitOpt),
+    #[structopt(
+        name = "status",
+        about = "show the status of a job started in the background"
+    )]
+    Status(StatusOpt),
+    #[structopt(
+        name = "attach",
+        about = "follow the output of a job started in the background and collect it"
+    )]
+    Attach(JobRefOpt),
+    #[structopt(name = "cancel", about = "cancel a job started in the background")]
+    Cancel(JobRefOpt),
+}
+
+impl Opt {
+    /// Parses the command line
+    ///
+    /// Without a subcommand, e.g. `gumpi -h HUB -j JOB -n 4`, the job is run
+    /// as with the `run` subcommand, like in the previous versions of gumpi.
+    pub fn from_cmdline() -> Self {
+        Self::from_iter(with_default_subcommand(std::env::args_os()))
+    }
+}
+
+/// Inserts the `run` subcommand if the arguments start with an option,
+/// except for the ones showing the help or the version
+fn with_default_subcommand(args: impl IntoIterator<Item = OsString>) -> Vec<OsString> {
+    let mut args: Vec<_> = args.into_iter().collect();
+    let is_run_option = args.get(1).map_or(false, |arg| {
+        let arg = arg.to_string_lossy();
+        arg.starts_with('-') && !["--help", "-V", "--version"].contains(&&*arg)
+    });
+    if is_run_option {
+        args.insert(1, "run".into());
+    }
+    args
+}
+
+#[derive(Debug, StructOpt)]
+pub struct RunOpt {
     #[structopt(short = "n", long = "numproc")]
     pub numproc: usize,
     #[structopt(short = "h", long = "hub")]
@@ -77,11 +446,144 @@ pub struct Opt {
     pub providers: Vec<NodeId>,
     #[structopt(long = "noclean")]
     pub noclean: bool,
+    #[structopt(
+        long = "repeat",
+        default_value = "1",
+        help = "run the job N times, reusing the session and the build"
+    )]
+    pub repeat: usize,
+    #[structopt(
+        long = "warmup",
+        default_value = "0",
+        help = "number of initial runs excluded from the timing statistics"
+    )]
+    pub warmup: usize,
+    #[structopt(
+        long = "timing-summary",
+        help = "write the timing statistics of the runs to the given file, in JSON format"
+    )]
+    pub timing_summary: Option<PathBuf>,
+    #[structopt(
+        long = "keep-outputs",
+        help = "save the execution output of every run to the given directory"
+    )]
+    pub keep_outputs: Option<PathBuf>,
+    #[structopt(
+        long = "resume",
+        help = "upload the latest checkpoint before running the job"
+    )]
+    pub resume: bool,
+    #[structopt(
+        long = "rank-logs",
+        help = "save the output of every rank to a separate file in the given directory"
+    )]
+    pub rank_logs: Option<PathBuf>,
+    #[structopt(
+        long = "phase-report",
+        help = "write the wall time of every phase of the job to the given file, in JSON format"
+    )]
+    pub phase_report: Option<PathBuf>,
+    #[structopt(
+        long = "build-logs",
+        help = "save the compilation output of every node to a separate file in the given directory"
+    )]
+    pub build_logs: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct BatchOpt {
+    #[structopt(short = "h", long = "hub")]
+    pub hub: SocketAddr,
+    #[structopt(short = "m", long = "manifest")]
+    pub manifest: PathBuf,
+    #[structopt(
+        short = "c",
+        long = "concurrency",
+        default_value = "1",
+        help = "maximum number of jobs running at the same time"
+    )]
+    pub concurrency: usize,
+    #[structopt(
+        short = "r",
+        long = "results",
+        default_value = "gumpi-results.json",
+        help = "the file to write the status of each job to, in JSON format"
+    )]
+    pub results: PathBuf,
+    #[structopt(long = "noclean")]
+    pub noclean: bool,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SubmitOpt {
+    #[structopt(short = "n", long = "numproc")]
+    pub numproc: usize,
+    #[structopt(short = "h", long = "hub")]
+    pub hub: SocketAddr,
+    #[structopt(short = "j", long = "job")]
+    pub jobconfig: PathBuf,
+    #[structopt(
+        long = "providers",
+        help = "explictly select which providers to use, by their node id"
+    )]
+    pub providers: Vec<NodeId>,
+    #[structopt(long = "noclean")]
+    pub noclean: bool,
+    #[structopt(
+        long = "state-dir",
+        help = "directory with the records of the background jobs, defaults to ~/.gumpi/jobs"
+    )]
+    pub state_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct StatusOpt {
+    #[structopt(help = "the id of the job, all the saved jobs are listed if missing")]
+    pub job_id: Option<String>,
+    #[structopt(
+        long = "state-dir",
+        help = "directory with the records of the background jobs, defaults to ~/.gumpi/jobs"
+    )]
+    pub state_dir: Option<PathBuf>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct JobRefOpt {
+    #[structopt(help = "the id of the job, as printed by `gumpi submit`")]
+    pub job_id: String,
+    #[structopt(
+        long = "state-dir",
+        help = "directory with the records of the background jobs, defaults to ~/.gumpi/jobs"
+    )]
+    pub state_dir: Option<PathBuf>,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    #[test]
+    fn test_default_subcommand() {
+        let args = |line: &str| line.split(' ').map(OsString::from).collect::<Vec<_>>();
+        let parse = |line: &str| Opt::from_iter_safe(with_default_subcommand(args(line)));
+
+        match parse("gumpi -h 127.0.0.1:61622 -j job.toml -n 12").unwrap() {
+            Opt::Run(opt) => {
+                assert_eq!(opt.numproc, 12);
+                assert_eq!(opt.jobconfig, Path::new("job.toml"));
+            }
+            opt => panic!("expected the run subcommand, got {:?}", opt),
+        }
+        match parse("gumpi batch -h 127.0.0.1:61622 -m queue.toml").unwrap() {
+            Opt::Batch(opt) => assert_eq!(opt.manifest, Path::new("queue.toml")),
+            opt => panic!("expected the batch subcommand, got {:?}", opt),
+        }
+        assert_eq!(
+            with_default_subcommand(args("gumpi --help")),
+            args("gumpi --help")
+        );
+    }
+
     #[test]
     fn test_jobconfig() {
         let config: JobConfig = toml::from_str(
@@ -106,6 +608,216 @@ mod tests {
 
         let sources = config.sources.unwrap();
         assert_eq!(sources.path, Path::new("prog.zip"));
+        assert_eq!(ArchiveFormat::from_path(&sources.path), ArchiveFormat::Zip);
         assert_eq!(sources.mode, BuildType::CMake);
+        assert_eq!(sources.build_on, BuildOn::Node);
+        assert!(config.retry.is_none());
+        assert_eq!(config.toolchain_check, ToolchainCheck::Warn);
+    }
+
+    #[test]
+    fn test_script_build() {
+        let config: JobConfig = toml::from_str(
+            r#"
+            progname = "prog"
+            args = []
+
+            [sources]
+            path = "prog.tar"
+            mode = "Script"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+
+        let config: JobConfig = toml::from_str(
+            r#"
+            progname = "prog"
+            args = []
+
+            [sources]
+            path = "prog.tar"
+            mode = "Script"
+            script = "build.sh"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let sources = config.sources.unwrap();
+        assert_eq!(sources.script.unwrap(), Path::new("build.sh"));
+
+        let config: JobConfig = toml::from_str(
+            r#"
+            progname = "prog"
+            args = []
+
+            [sources]
+            path = "prog.tar"
+            mode = "Script"
+            script = "build.sh"
+            build_dir = "build"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_checkpoint_config() {
+        let config = |interval: u64| -> JobConfig {
+            toml::from_str(&format!(
+                r#"
+                progname = "prog"
+                args = []
+
+                [checkpoint]
+                path = "/output/checkpoint"
+                target = "checkpoint.tar"
+                interval = {}
+                "#,
+                interval
+            ))
+            .unwrap()
+        };
+        assert!(config(600).validate().is_ok());
+        assert!(config(0).validate().is_err());
+        assert!(config(MIN_CHECKPOINT_INTERVAL - 1).validate().is_err());
+    }
+
+    #[test]
+    fn test_input_config() {
+        let config: JobConfig = toml::from_str(
+            r#"
+            progname = "prog"
+            args = []
+
+            [input]
+            source = "input.tar"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.input.len(), 1);
+        assert_eq!(config.input[0].destination, Path::new("/input"));
+        assert_eq!(config.input[0].scope, InputScope::All);
+
+        let config: JobConfig = toml::from_str(
+            r#"
+            progname = "prog"
+            args = []
+
+            [[input]]
+            source = "dataset.tar.zst"
+            destination = "/data"
+
+            [[input]]
+            source = "params.tar"
+            scope = "root"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.input.len(), 2);
+        assert_eq!(config.input[0].destination, Path::new("/data"));
+        assert_eq!(config.input[1].scope, InputScope::Root);
+
+        let config: JobConfig = toml::from_str(
+            r#"
+            progname = "prog"
+            args = []
+
+            [[input]]
+            source = "input.tar"
+            destination = "data"
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_output_config() {
+        let config: JobConfig = toml::from_str(
+            r#"
+            progname = "prog"
+            args = []
+
+            [output]
+            target = "outs"
+            format = "tar.gz"
+            scope = "all"
+            extract = "results"
+            merge = true
+            include = ["*.csv", "summary.txt"]
+            exclude = ["scratch/*"]
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        let output = config.output.unwrap();
+        assert_eq!(output.source, Path::new("/output"));
+        assert_eq!(output.format(), ArchiveFormat::TarGz);
+        assert_eq!(output.scope, OutputScope::All);
+        assert_eq!(output.on_conflict, OnConflict::Fail);
+        assert_eq!(output.filter.include, vec!["*.csv", "summary.txt"]);
+        assert_eq!(output.filter.exclude, vec!["scratch/*"]);
+
+        let config: JobConfig = toml::from_str(
+            r#"
+            progname = "prog"
+            args = []
+
+            [output]
+            target = "outs.tar"
+            merge = true
+            "#,
+        )
+        .unwrap();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_retry_config() {
+        let config: JobConfig = toml::from_str(
+            r#"
+            progname = "prog"
+            args = []
+
+            [retry]
+            max_attempts = 3
+            exclude_failed = true
+            "#,
+        )
+        .unwrap();
+
+        let retry = config.retry.unwrap();
+        assert_eq!(retry.max_attempts, 3);
+        assert_eq!(
+            retry.retry_on,
+            vec![FailureClass::Node, FailureClass::Network]
+        );
+        assert!(retry.exclude_failed);
+    }
+
+    #[test]
+    fn test_jobqueue() {
+        let queue: JobQueue = toml::from_str(
+            r#"
+            [[job]]
+            config = "game-life.toml"
+            numproc = 12
+
+            [[job]]
+            config = "heat_solver.toml"
+            numproc = 4
+            providers = []
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(queue.jobs.len(), 2);
+        assert_eq!(queue.jobs[0].config, Path::new("game-life.toml"));
+        assert_eq!(queue.jobs[0].numproc, 12);
+        assert!(queue.jobs[0].providers.is_empty());
+        assert_eq!(queue.jobs[1].numproc, 4);
     }
 }
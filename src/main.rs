@@ -1,13 +1,27 @@
 #![warn(clippy::all)]
 #![warn(rust_2018_idioms)]
 
+mod archive;
+mod batch;
+mod buildlogs;
+mod checkpoint;
+mod detached;
 mod error;
 mod jobconfig;
+mod progress;
+mod ranklogs;
+mod retry;
 mod session;
+mod timing;
+mod toolchain;
+mod transfer;
 
 use crate::{
-    jobconfig::{JobConfig, Opt},
-    session::mpi::SessionMPI,
+    jobconfig::{JobConfig, Opt, RunOpt},
+    ranklogs::RankOutput,
+    retry::ExecParams,
+    session::mpi::{self, SessionMPI},
+    timing::{PhaseReport, SharedPhaseReport, TimingSummary},
 };
 use actix::prelude::*;
 use failure::{format_err, Fallible, ResultExt};
@@ -15,23 +29,38 @@ use failure_ext::FutureExt;
 use futures::{
     future::{self, Either},
     prelude::*,
+    stream,
 };
+use gu_client::r#async::HubConnection;
 use log::{debug, error, info};
-use std::env;
+use std::{
+    cmp,
+    collections::BTreeMap,
+    env, fs, iter,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::Instant,
+};
 use structopt::StructOpt;
 use tokio_ctrlc_error::{AsyncCtrlc, KeyboardInterrupt};
 
+fn is_interrupted(e: &failure::Error) -> bool {
+    e.find_root_cause()
+        .downcast_ref::<KeyboardInterrupt>()
+        .is_some()
+}
+
+fn format_error_chain(e: &failure::Error) -> String {
+    let causes: Vec<_> = e.iter_chain().map(ToString::to_string).collect();
+    causes.join(": ")
+}
+
 fn show_error(e: &failure::Error) {
-    match e.find_root_cause().downcast_ref::<KeyboardInterrupt>() {
-        Some(_) => eprintln!("Execution interrupted..."),
-        None => {
-            eprint!("Error");
-            for cause in e.iter_chain() {
-                eprint!(": {}", cause);
-            }
-            eprintln!("");
-        }
-    };
+    if is_interrupted(e) {
+        eprintln!("Execution interrupted...");
+    } else {
+        eprintln!("Error: {}", format_error_chain(e));
+    }
     std::process::exit(1);
 }
 
@@ -46,13 +75,20 @@ fn init_logger() {
     if env::var("RUST_LOG").is_err() {
         env::set_var("RUST_LOG", "info")
     }
-    env_logger::init()
+    // The log lines are printed above the progress display
+    let logger = env_logger::Builder::from_default_env().build();
+    let max_level = logger.filter();
+    log::set_boxed_logger(Box::new(progress::Logger(logger)))
+        .expect("the logger has already been set");
+    log::set_max_level(max_level);
 }
 
+/// Returns: the SHA-256 digests of the transferred archives
 fn gumpi_async(
-    opt: Opt,
+    hub_conn: &HubConnection,
+    opt: RunOpt,
     config: JobConfig,
-) -> Fallible<impl Future<Item = (), Error = failure::Error>> {
+) -> Fallible<impl Future<Item = BTreeMap<String, String>, Error = failure::Error>> {
     let cpus_requested = opt.numproc;
     let prov_filter = if opt.providers.is_empty() {
         None
@@ -72,128 +108,442 @@ fn gumpi_async(
         .to_owned();
     let noclean = opt.noclean;
 
-    // The initialization of the provider may take time,
-    // so check if the file exists at all in advance
-    if let Some(input) = &config.input {
-        let input_path = jobconfig_dir.join(&input.source);
-        if !input_path.is_file() {
-            return Err(format_err!(
-                "The input data, {}, doesn't exist",
-                input_path.to_string_lossy()
-            ));
-        }
+    let resume_cfg = if opt.resume {
+        let cfg = config.checkpoint.clone().ok_or_else(|| {
+            format_err!("--resume requires the [checkpoint] section in the job config")
+        })?;
+        Some(cfg)
+    } else {
+        None
+    };
+
+    let repeat = opt.repeat;
+    let warmup = opt.warmup;
+    if repeat == 0 {
+        return Err(format_err!("The number of repetitions must be at least 1"));
+    }
+    if warmup >= repeat {
+        return Err(format_err!(
+            "The number of warm-up runs ({}) must be smaller than the number of repetitions ({})",
+            warmup,
+            repeat
+        ));
+    }
+    let timing_summary = opt.timing_summary;
+    let keep_outputs = opt.keep_outputs;
+    if let Some(dir) = &keep_outputs {
+        fs::create_dir_all(dir).context(format!(
+            "creating the output directory {}",
+            dir.to_string_lossy()
+        ))?;
     }
 
-    let future = SessionMPI::init(opt.hub, prov_filter)
+    let rank_logs = opt.rank_logs;
+    // With repeated runs, the logs of every run go to a separate subdirectory
+    let rank_logs_dir = move |run: usize| {
+        rank_logs.as_ref().map(|dir| {
+            if repeat > 1 {
+                dir.join(format!("run-{}", run + 1))
+            } else {
+                dir.clone()
+            }
+        })
+    };
+
+    let build_logs = opt.build_logs;
+    let phase_report_path = opt.phase_report;
+    let report = SharedPhaseReport::default();
+    let final_report = Rc::clone(&report);
+
+    check_input(&config, &jobconfig_dir)?;
+
+    let init = SessionMPI::init(hub_conn, prov_filter);
+    let future = timing::timed(&report, "init".to_owned(), init)
         .ctrlc_as_error() // This is not a bug - we have a second `.ctrlc_as_error()`
         // inside the `and_then`
         .context("initializing session")
         .and_then(move |session| {
-            use std::rc::Rc;
             let session = Rc::new(session);
             let mut session_clone = Rc::clone(&session);
 
-            info!("available cores: {}", session.total_cpus());
-            let cpus_available = session.total_cpus();
-            if cpus_available < cpus_requested {
-                return Either::A(future::err(format_err!(
-                    "Not enough CPUs available: requested: {}, available: {}",
-                    cpus_requested,
-                    cpus_available
-                )));
+            if let Err(e) = check_cpus(&session, cpus_requested) {
+                return Either::A(future::err(e));
             }
-            info!("Compiling the sources...");
-            // impl Future<Item = bool>
-            // * `true` if we have compiled the sources on the provider node
-            // * `false` otherwise
-            let deploy_future = if let Some(sources) = config.sources.clone() {
-                Either::A(
-                    session
-                        .deploy(jobconfig_dir.clone(), sources)
-                        .context("deploying the sources")
-                        .and_then(|depl| {
-                            for comp in depl.logs {
-                                let logs = comp.logs.join("\n------------------\n");
+            let resume_session = Rc::clone(&session);
+            let output_report = Rc::clone(&report);
+            let cleanup_report = Rc::clone(&report);
+
+            Either::B(
+                prepare_session(&session, &config, jobconfig_dir, build_logs, &report)
+                    .and_then(move |bindir| {
+                        let resume = match &resume_cfg {
+                            Some(cfg) => Either::A(checkpoint::push(&resume_session, cfg)),
+                            None => Either::B(future::ok(())),
+                        };
+                        resume.map(move |()| bindir)
+                    })
+                    .and_then(move |bindir| {
+                        let libdir = job_libdir(&config);
+                        let mut mpiargs = config.mpiargs.unwrap_or_default();
+                        if rank_logs_dir(0).is_some() {
+                            mpiargs.push(ranklogs::TAG_OUTPUT_ARG.to_owned());
+                        }
+                        let params = ExecParams {
+                            nproc: cpus_requested,
+                            progname: config.progname,
+                            args: config.args,
+                            mpiargs,
+                            bindir,
+                            libdir,
+                        };
+                        let error_logs_dir = rank_logs_dir.clone();
+                        let retry_policy = config.retry;
+                        let checkpoint_cfg = config.checkpoint;
+                        let exec_session = Rc::clone(&session);
+                        let exec_report = Rc::clone(&report);
+
+                        // The runs are executed one after another, so that they
+                        // don't compete for the resources
+                        stream::iter_ok(0..repeat)
+                            .and_then(move |run| {
+                                let logs_dir = error_logs_dir(run);
+                                let start = Instant::now();
+                                let phase = if repeat > 1 {
+                                    format!("exec (run {})", run + 1)
+                                } else {
+                                    "exec".to_owned()
+                                };
+                                let exec = retry::exec_with_retry(
+                                    Rc::clone(&exec_session),
+                                    params.clone(),
+                                    retry_policy.clone(),
+                                    checkpoint_cfg.clone(),
+                                );
+                                timing::timed(&exec_report, phase, exec)
+                                    .map(move |(output, attempts)| {
+                                        (run, start.elapsed(), output, attempts)
+                                    })
+                                    .map_err(move |e| {
+                                        if let Some(dir) = logs_dir {
+                                            ranklogs::save_from_error(&e, &dir);
+                                        }
+                                        e
+                                    })
+                            })
+                            .and_then(move |(run, elapsed, output, attempts)| -> Fallible<_> {
                                 info!(
-                                    "Provider {} compilation output:\n{}",
-                                    comp.node.to_string(),
-                                    logs
+                                    "Run {}/{} finished in {:.3} s",
+                                    run + 1,
+                                    repeat,
+                                    timing::as_secs(elapsed)
                                 );
+                                if let Some(dir) = &keep_outputs {
+                                    let path = dir.join(format!("run-{}.txt", run + 1));
+                                    fs::write(&path, &output).context(format!(
+                                        "saving the output of run {} to {}",
+                                        run + 1,
+                                        path.to_string_lossy()
+                                    ))?;
+                                } else if repeat == 1 {
+                                    println!("Execution output:\n{}", output);
+                                }
+                                if let Some(dir) = rank_logs_dir(run) {
+                                    RankOutput::split(&output).save_and_summarize(&dir)?;
+                                }
+                                Ok((elapsed, attempts))
+                            })
+                            .collect()
+                            .join(future::ok(session))
+                    })
+                    .and_then(move |(runs, session)| -> Fallible<_> {
+                        let (durations, attempts): (Vec<_>, Vec<_>) = runs.into_iter().unzip();
+                        for (run, attempts) in attempts.iter().enumerate() {
+                            if attempts.len() > 1 {
+                                println!("Execution attempts of run {}:", run + 1);
+                                for attempt in attempts {
+                                    println!("  {}", attempt);
+                                }
                             }
-                            Ok(true)
-                        }),
-                )
-            } else {
-                Either::B(future::ok(false))
-            };
-
-            let upload_input = if let Some(input) = config.input.clone() {
-                let input_path = jobconfig_dir.join(input.source);
-                Either::A(session.upload_input(input_path))
-            } else {
-                Either::B(future::ok(()))
-            };
-
-            let deploy_keys = session
-                .deploy_keys()
-                .into_future()
-                .flatten()
-                .context("deploying SSH keys");
+                        }
 
-            Either::B(
-                deploy_future
-                    .join3(upload_input, deploy_keys)
-                    .and_then(move |(deployed, (), ())| {
-                        session
-                            .exec(
-                                cpus_requested,
-                                config.progname,
-                                config.args,
-                                config.mpiargs.unwrap_or_default(),
-                                deployed,
-                            )
-                            .context("program execution")
-                            .join(future::ok(session))
+                        if repeat > 1 || timing_summary.is_some() {
+                            // warmup < repeat has been checked, so there are runs left
+                            let summary = TimingSummary::new(&durations, warmup)
+                                .expect("no runs left after the warm-up");
+                            println!("Timing summary:\n{}", summary);
+                            if let Some(path) = &timing_summary {
+                                let json = serde_json::to_string_pretty(&summary)?;
+                                fs::write(path, json).context(format!(
+                                    "writing the timing summary to {}",
+                                    path.to_string_lossy()
+                                ))?;
+                            }
+                        }
+                        Ok(session)
                     })
-                    .and_then(|(output, session)| {
-                        println!("Execution output:\n{}", output);
+                    .and_then(move |session| {
                         if let Some(outs) = output_cfg {
-                            Either::A(session.retrieve_output(&outs).context("retrieving output"))
+                            let retrieve =
+                                session.retrieve_output(&outs).context("retrieving output");
+                            Either::A(timing::timed(
+                                &output_report,
+                                "output retrieval".to_owned(),
+                                retrieve,
+                            ))
                         } else {
                             Either::B(future::ok(()))
                         }
                     })
                     .ctrlc_as_error()
                     .then(move |fut| {
-                        // At this point, there should be no other session references
-                        // remaining. If it isn't so, we want to stay on the safe side
-                        // and will not attempt to cleanup.
-                        info!("Cleaning up");
-                        let cleanup = if noclean {
-                            Either::A(future::ok(()))
-                        } else {
-                            match Rc::get_mut(&mut session_clone) {
-                                Some(sess) => Either::B(sess.close().from_err()),
-                                None => Either::A(future::err(format_err!(
-                                    "Hub session references remaining, \
-                                     cannot safely close the session..."
-                                ))),
-                            }
-                        };
-
-                        cleanup
-                            .map_err(|e| error!("Error cleaning up: {}", e))
-                            .then(|_| fut)
+                        cleanup_report.borrow_mut().checksums = session_clone.digests();
+                        let close = cleanup(&mut session_clone, noclean);
+                        timing::timed(&cleanup_report, "cleanup".to_owned(), close).then(|_| fut)
                     }),
             )
+        })
+        .then(move |res| {
+            let report = final_report.borrow();
+            println!("Phase timing report:\n{}", report);
+            let saved = match &phase_report_path {
+                Some(path) => save_phase_report(&report, path),
+                None => Ok(()),
+            };
+            // The failure of the job takes precedence over the failure to save the report
+            res.and(saved).map(|()| report.checksums.clone())
         });
     Ok(future)
 }
 
+/// The initialization of the provider may take time,
+/// so check if the input data and the prebuilt binary exist at all in advance
+fn check_input(config: &JobConfig, jobconfig_dir: &Path) -> Fallible<()> {
+    for input in &config.input {
+        let input_path = jobconfig_dir.join(&input.source);
+        if !input_path.is_file() {
+            return Err(format_err!(
+                "The input data, {}, doesn't exist",
+                input_path.to_string_lossy()
+            ));
+        }
+    }
+    if let Some(binary) = &config.binary {
+        for path in iter::once(&binary.path).chain(&binary.libraries) {
+            let path = jobconfig_dir.join(path);
+            if !path.is_file() {
+                return Err(format_err!(
+                    "The binary file, {}, doesn't exist",
+                    path.to_string_lossy()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns: the directory of the libraries uploaded along with the prebuilt binary, if any
+fn job_libdir(config: &JobConfig) -> Option<String> {
+    match &config.binary {
+        Some(binary) if !binary.libraries.is_empty() => Some(mpi::APP_LIBRARY_PATH.to_owned()),
+        _ => None,
+    }
+}
+
+fn check_cpus(session: &SessionMPI, cpus_requested: usize) -> Fallible<()> {
+    info!("available cores: {}", session.total_cpus());
+    let cpus_available = session.total_cpus();
+    if cpus_available < cpus_requested {
+        return Err(format_err!(
+            "Not enough CPUs available: requested: {}, available: {}",
+            cpus_requested,
+            cpus_available
+        ));
+    }
+    Ok(())
+}
+
+fn save_phase_report(report: &PhaseReport, path: &Path) -> Fallible<()> {
+    let json = serde_json::to_string_pretty(report)?;
+    fs::write(path, json).context(format!(
+        "writing the phase timing report to {}",
+        path.to_string_lossy()
+    ))?;
+    Ok(())
+}
+
+/// Builds the sources or uploads the binary, uploads the input data and deploys the SSH keys
+///
+/// The times of these phases are recorded in `report`.
+///
+/// Returns:
+/// * the directory containing the binary, if we have compiled the sources
+///   on the provider node or uploaded a prebuilt binary
+/// * `None` otherwise
+fn prepare_session(
+    session: &SessionMPI,
+    config: &JobConfig,
+    jobconfig_dir: PathBuf,
+    build_logs: Option<PathBuf>,
+    report: &SharedPhaseReport,
+) -> impl Future<Item = Option<String>, Error = failure::Error> {
+    // The directory of the program, unless it's expected on the PATH
+    let bindir = match (&config.sources, &config.binary) {
+        (Some(sources), _) => Some(mpi::binary_dir(sources)),
+        (None, Some(_)) => Some(mpi::APP_SOURCES_PATH.to_owned()),
+        (None, None) => None,
+    };
+    let check_binary = session
+        .check_binary(&config.progname, bindir.as_ref().map(String::as_str))
+        .context("checking the program");
+
+    let deploy_report = Rc::clone(report);
+    let deploy_future = if let Some(sources) = config.sources.clone() {
+        info!("Compiling the sources...");
+        let error_logs_dir = build_logs.clone();
+        let deploy = session
+            .deploy(jobconfig_dir.clone(), sources)
+            .map_err(move |e| -> failure::Error {
+                if let Some(dir) = &error_logs_dir {
+                    buildlogs::save_from_error(&e, dir);
+                }
+                e.context("deploying the sources").into()
+            })
+            .and_then(move |depl| -> Fallible<_> {
+                for comp in &depl.logs {
+                    deploy_report.borrow_mut().record_provider(
+                        "deploy",
+                        comp.node.to_string(),
+                        comp.duration,
+                    );
+                }
+                match &build_logs {
+                    Some(dir) => buildlogs::save_all(&depl.logs, dir)?,
+                    None => {
+                        for comp in depl.logs {
+                            info!(
+                                "Provider {} compilation output:\n{}",
+                                comp.node.to_string(),
+                                buildlogs::join(&comp.logs)
+                            );
+                        }
+                    }
+                }
+                Ok(())
+            });
+        Either::A(timing::timed(report, "deploy".to_owned(), deploy))
+    } else if let Some(binary) = &config.binary {
+        let upload = session
+            .deploy_binary(&jobconfig_dir, binary, &config.progname)
+            .context("deploying the binary")
+            .map(move |times| {
+                for (node, duration) in times {
+                    deploy_report.borrow_mut().record_provider(
+                        "binary upload",
+                        node.to_string(),
+                        duration,
+                    );
+                }
+            });
+        Either::B(Either::A(timing::timed(
+            report,
+            "binary upload".to_owned(),
+            upload,
+        )))
+    } else {
+        Either::B(Either::B(future::ok(())))
+    };
+
+    let input_report = Rc::clone(report);
+    let uploads: Vec<_> = config
+        .input
+        .iter()
+        .map(|input| {
+            session.upload_input(
+                jobconfig_dir.join(&input.source),
+                input.destination.to_string_lossy().into_owned(),
+                input.scope,
+            )
+        })
+        .collect();
+    let upload_input = if uploads.is_empty() {
+        Either::B(future::ok(()))
+    } else {
+        // The archives are uploaded in parallel, so the time on every provider
+        // is the longest of its downloads
+        let upload = future::join_all(uploads).map(move |uploads| {
+            let mut times = BTreeMap::new();
+            for (node, duration) in uploads.into_iter().flatten() {
+                let time = times.entry(node.to_string()).or_insert(duration);
+                *time = cmp::max(*time, duration);
+            }
+            for (node, duration) in times {
+                input_report
+                    .borrow_mut()
+                    .record_provider("input upload", node, duration);
+            }
+        });
+        Either::A(timing::timed(report, "input upload".to_owned(), upload))
+    };
+
+    let deploy_keys = session
+        .deploy_keys()
+        .into_future()
+        .flatten()
+        .context("deploying SSH keys");
+    let deploy_keys = timing::timed(report, "key deployment".to_owned(), deploy_keys);
+
+    let preflight =
+        toolchain::check(session, config.toolchain_check).context("checking the toolchain");
+    let preflight = timing::timed(report, "preflight".to_owned(), preflight);
+
+    // The program is checked only once it's deployed
+    let deploy_future = deploy_future.and_then(move |()| check_binary);
+    preflight.and_then(move |()| {
+        deploy_future
+            .join3(upload_input, deploy_keys)
+            .map(move |((), (), ())| bindir)
+    })
+}
+
+/// Closes the session, unless `noclean` is set
+fn cleanup(session: &mut Rc<SessionMPI>, noclean: bool) -> impl Future<Item = (), Error = ()> {
+    // At this point, there should be no other session references
+    // remaining. If it isn't so, we want to stay on the safe side
+    // and will not attempt to cleanup.
+    info!("Cleaning up");
+    let cleanup = if noclean {
+        Either::A(future::ok(()))
+    } else {
+        match Rc::get_mut(session) {
+            Some(sess) => Either::B(sess.close().from_err()),
+            None => Either::A(future::err(format_err!(
+                "Hub session references remaining, \
+                 cannot safely close the session..."
+            ))),
+        }
+    };
+
+    cleanup.map_err(|e| error!("Error cleaning up: {}", e))
+}
+
 fn run() -> Fallible<()> {
-    let opt = Opt::from_args();
-    let config = JobConfig::from_file(&opt.jobconfig).context("reading job config")?;
+    let opt = Opt::from_cmdline();
 
     let mut sys = System::new("gumpi");
-    sys.block_on(gumpi_async(opt, config)?)
+    match opt {
+        Opt::Run(opt) => {
+            let config = JobConfig::from_file(&opt.jobconfig).context("reading job config")?;
+            let hub_conn = SessionMPI::connect(opt.hub)?;
+            sys.block_on(gumpi_async(&hub_conn, opt, config)?.map(|_| ()))
+        }
+        Opt::Batch(opt) => sys.block_on(batch::run_batch(opt)?),
+        Opt::Submit(opt) => {
+            let config = JobConfig::from_file(&opt.jobconfig).context("reading job config")?;
+            let hub_conn = SessionMPI::connect(opt.hub)?;
+            sys.block_on(detached::submit(&hub_conn, opt, config)?)
+        }
+        Opt::Status(opt) => sys.block_on(detached::status(opt)?),
+        Opt::Attach(opt) => sys.block_on(detached::attach(opt)?),
+        Opt::Cancel(opt) => sys.block_on(detached::cancel(opt)?),
+    }
 }
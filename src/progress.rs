@@ -0,0 +1,373 @@
+//! The progress of the long phases of the job
+//!
+//! If stdout is a terminal supporting the cursor movement, the transfers and
+//! the states of the nodes are shown as a block of lines at the bottom, redrawn
+//! in place. Otherwise, the progress is reported with plain log lines.
+
+use crate::{timing::as_secs, transfer::human_size};
+use log::{info, Log, Metadata, Record};
+use std::{
+    cell::RefCell,
+    env,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
+
+/// The width of the progress bar, in characters
+const BAR_WIDTH: usize = 24;
+
+/// The width of the terminal, if it can't be determined
+const DEFAULT_WIDTH: usize = 80;
+
+/// The minimum time between the redraws of the display
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The number of log lines during a transfer, without a terminal
+const LOG_STEPS: u64 = 10;
+
+/// The lines of the progress display
+struct Board {
+    interactive: bool,
+    /// the lines, by their ids, in the order of their creation
+    lines: Vec<(u64, String)>,
+    next_id: u64,
+    /// the number of lines on the screen
+    drawn: usize,
+    last_draw: Option<Instant>,
+}
+
+thread_local! {
+    static BOARD: RefCell<Board> = RefCell::new(Board {
+        interactive: is_terminal(),
+        lines: vec![],
+        next_id: 0,
+        drawn: 0,
+        last_draw: None,
+    });
+}
+
+impl Board {
+    fn add(&mut self, line: String) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.lines.push((id, line));
+        self.redraw(true);
+        id
+    }
+
+    fn set(&mut self, id: u64, line: String, force: bool) {
+        if let Some(entry) = self.lines.iter_mut().find(|(i, _)| *i == id) {
+            entry.1 = line;
+        }
+        self.redraw(force);
+    }
+
+    fn remove(&mut self, id: u64) {
+        self.lines.retain(|(i, _)| *i != id);
+        self.redraw(true);
+    }
+
+    /// Moves the cursor back to the first line of the display and clears it
+    fn clear(&mut self, out: &mut impl Write) -> io::Result<()> {
+        if self.drawn > 0 {
+            write!(out, "\x1b[{}A\x1b[J", self.drawn)?;
+            self.drawn = 0;
+        }
+        Ok(())
+    }
+
+    fn draw(&mut self, out: &mut impl Write) -> io::Result<()> {
+        self.clear(out)?;
+        // The lines mustn't wrap, otherwise the display can't be cleared.
+        // The last column is left empty, since some terminals wrap after filling it.
+        let width = terminal_width().unwrap_or(DEFAULT_WIDTH).saturating_sub(1);
+        for (_, line) in &self.lines {
+            let line: String = line.chars().take(width).collect();
+            writeln!(out, "{}", line)?;
+        }
+        self.drawn = self.lines.len();
+        self.last_draw = Some(Instant::now());
+        out.flush()
+    }
+
+    /// Redraws the display, at most every `REDRAW_INTERVAL` unless forced
+    fn redraw(&mut self, force: bool) {
+        if !self.interactive {
+            return;
+        }
+        let due = self
+            .last_draw
+            .map_or(true, |last| last.elapsed() >= REDRAW_INTERVAL);
+        if force || due {
+            // The progress display is best-effort
+            let _ = self.draw(&mut io::stdout().lock());
+        }
+    }
+}
+
+/// Checks if stdout is a terminal which can move the cursor
+fn is_terminal() -> bool {
+    let capable = env::var_os("TERM").map_or(false, |term| term != "dumb");
+    capable && atty::is(atty::Stream::Stdout)
+}
+
+/// Returns: the number of columns of the terminal on stdout, or `$COLUMNS`
+fn terminal_width() -> Option<usize> {
+    window_columns().or_else(|| {
+        env::var("COLUMNS")
+            .ok()
+            .and_then(|columns| columns.parse().ok())
+            .filter(|&columns| columns > 0)
+    })
+}
+
+#[cfg(unix)]
+fn window_columns() -> Option<usize> {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    // TIOCGWINSZ only writes to the `winsize` passed
+    let ret = unsafe {
+        libc::ioctl(
+            libc::STDOUT_FILENO,
+            libc::TIOCGWINSZ,
+            &mut size as *mut libc::winsize,
+        )
+    };
+    if ret == 0 && size.ws_col > 0 {
+        Some(usize::from(size.ws_col))
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+fn window_columns() -> Option<usize> {
+    None
+}
+
+fn is_interactive() -> bool {
+    BOARD
+        .try_with(|board| board.borrow().interactive)
+        .unwrap_or(false)
+}
+
+fn with_board(f: impl FnOnce(&mut Board)) {
+    let _ = BOARD.try_with(|board| {
+        if let Ok(mut board) = board.try_borrow_mut() {
+            f(&mut board)
+        }
+    });
+}
+
+/// Clears the display for the duration of `f`, so that whatever `f` prints
+/// appears above it
+fn suspend(f: impl FnOnce()) {
+    let mut cleared = false;
+    with_board(|board| {
+        if board.interactive && board.drawn > 0 {
+            cleared = board.clear(&mut io::stdout().lock()).is_ok();
+        }
+    });
+    f();
+    if cleared {
+        with_board(|board| board.redraw(true));
+    }
+}
+
+/// Passes the records to the inner logger, keeping them above the progress display
+pub struct Logger<L>(pub L);
+
+impl<L: Log> Log for Logger<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if self.0.enabled(record.metadata()) {
+            suspend(|| self.0.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+/// The progress of a transfer of `total` bytes
+///
+/// Without a terminal, the progress is logged every 10% of the total size.
+#[derive(Debug)]
+pub struct Transfer {
+    name: String,
+    total: u64,
+    done: u64,
+    /// the number of steps logged so far
+    logged: u64,
+    line: Option<u64>,
+}
+
+impl Transfer {
+    /// `total` is 0 if the size is unknown
+    pub fn new(name: String, total: u64) -> Self {
+        let mut transfer = Transfer {
+            name,
+            total,
+            done: 0,
+            logged: 0,
+            line: None,
+        };
+        if is_interactive() {
+            let line = transfer.line();
+            with_board(|board| transfer.line = Some(board.add(line)));
+        }
+        transfer
+    }
+
+    pub fn advance(&mut self, bytes: u64) {
+        self.done += bytes;
+        if let Some(id) = self.line {
+            let line = self.line();
+            let finished = self.total > 0 && self.done >= self.total;
+            with_board(|board| board.set(id, line, finished));
+        }
+
+        if self.total == 0 {
+            return;
+        }
+        let step = (self.done * LOG_STEPS / self.total).min(LOG_STEPS);
+        if step > self.logged {
+            self.logged = step;
+            // The bar shows the intermediate steps
+            if self.line.is_none() || step == LOG_STEPS {
+                info!(
+                    "{}: {}% ({} of {})",
+                    self.name,
+                    step * 100 / LOG_STEPS,
+                    human_size(self.done),
+                    human_size(self.total)
+                );
+            }
+        }
+    }
+
+    fn line(&self) -> String {
+        if self.total == 0 {
+            return format!("{}: {}", self.name, human_size(self.done));
+        }
+        let done = self.done.min(self.total);
+        let filled = (done * BAR_WIDTH as u64 / self.total) as usize;
+        format!(
+            "[{}{}] {:>3}% {:>10} of {:<10} {}",
+            "#".repeat(filled),
+            "-".repeat(BAR_WIDTH - filled),
+            done * 100 / self.total,
+            human_size(done),
+            human_size(self.total),
+            self.name
+        )
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        if let Some(id) = self.line {
+            with_board(|board| board.remove(id));
+        }
+    }
+}
+
+/// The states of the nodes during a phase, e.g. the compilation
+///
+/// Without a terminal, every change of the state is logged.
+#[derive(Debug)]
+pub struct Nodes {
+    phase: String,
+    nodes: Vec<NodeState>,
+}
+
+#[derive(Debug)]
+struct NodeState {
+    node: String,
+    state: RefCell<String>,
+    line: Option<u64>,
+}
+
+impl Nodes {
+    pub fn new(phase: String, nodes: impl IntoIterator<Item = String>) -> Self {
+        let interactive = is_interactive();
+        let nodes = nodes
+            .into_iter()
+            .map(|node| {
+                let state = "waiting".to_owned();
+                let mut line = None;
+                if interactive {
+                    let text = format!("{} on {}: {}", phase, node, state);
+                    with_board(|board| line = Some(board.add(text)));
+                }
+                NodeState {
+                    node,
+                    state: RefCell::new(state),
+                    line,
+                }
+            })
+            .collect();
+        Nodes { phase, nodes }
+    }
+
+    pub fn set(&self, node: &str, state: &str) {
+        let entry = match self.nodes.iter().find(|entry| entry.node == node) {
+            Some(entry) => entry,
+            None => return,
+        };
+        *entry.state.borrow_mut() = state.to_owned();
+        let text = format!("{} on {}: {}", self.phase, node, state);
+        match entry.line {
+            Some(id) => with_board(|board| board.set(id, text, true)),
+            None => info!("{}", text),
+        }
+    }
+
+    /// Marks the node as done after `duration`
+    pub fn done(&self, node: &str, duration: Duration) {
+        self.set(node, &format!("done in {:.1} s", as_secs(duration)));
+    }
+}
+
+impl Drop for Nodes {
+    fn drop(&mut self) {
+        for entry in &self.nodes {
+            if let Some(id) = entry.line {
+                with_board(|board| board.remove(id));
+                // The final states are kept in the log
+                info!("{} on {}: {}", self.phase, entry.node, entry.state.borrow());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transfer_line() {
+        let mut transfer = Transfer {
+            name: "Uploading input.tar".to_owned(),
+            total: 4096,
+            done: 0,
+            logged: 0,
+            line: None,
+        };
+        transfer.advance(1024);
+        assert_eq!(
+            transfer.line(),
+            format!(
+                "[{}{}]  25%    1.0 KiB of 4.0 KiB    Uploading input.tar",
+                "#".repeat(6),
+                "-".repeat(18)
+            )
+        );
+
+        transfer.total = 0;
+        assert_eq!(transfer.line(), "Uploading input.tar: 1.0 KiB");
+    }
+}
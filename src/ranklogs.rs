@@ -0,0 +1,152 @@
+use crate::error::Error;
+use failure::{Fallible, ResultExt};
+use log::{info, warn};
+use std::{collections::BTreeMap, fs, path::Path};
+
+/// The mpirun option prefixing every line of the output with the rank, e.g.
+/// `[1,3]<stderr>:message`
+pub const TAG_OUTPUT_ARG: &str = "--tag-output";
+
+/// The output of mpirun, split by the rank
+#[derive(Debug, Default)]
+pub struct RankOutput {
+    pub stdout: BTreeMap<usize, String>,
+    pub stderr: BTreeMap<usize, String>,
+    /// lines which don't come from any rank, e.g. the messages of mpirun itself
+    pub untagged: String,
+}
+
+/// Parses a line tagged by `--tag-output`
+///
+/// Returns: the rank, whether it's stderr and the contents of the line
+fn parse_tagged(line: &str) -> Option<(usize, bool, &str)> {
+    if !line.starts_with('[') {
+        return None;
+    }
+    let end = line.find(']')?;
+    let mut ids = line[1..end].split(',');
+    let _jobid = ids.next()?;
+    let rank = ids.next()?.trim().parse().ok()?;
+
+    let rest = &line[end + 1..];
+    if rest.starts_with("<stdout>:") {
+        Some((rank, false, &rest["<stdout>:".len()..]))
+    } else if rest.starts_with("<stderr>:") {
+        Some((rank, true, &rest["<stderr>:".len()..]))
+    } else {
+        None
+    }
+}
+
+impl RankOutput {
+    pub fn split(output: &str) -> Self {
+        let mut ret = Self::default();
+        for line in output.lines() {
+            let buf = match parse_tagged(line) {
+                Some((rank, false, content)) => {
+                    Some((ret.stdout.entry(rank).or_insert_with(String::new), content))
+                }
+                Some((rank, true, content)) => {
+                    Some((ret.stderr.entry(rank).or_insert_with(String::new), content))
+                }
+                None => None,
+            };
+            let (buf, content) = buf.unwrap_or((&mut ret.untagged, line));
+            buf.push_str(content);
+            buf.push('\n');
+        }
+        ret
+    }
+
+    /// Returns: the ranks which wrote anything to stderr
+    pub fn stderr_ranks(&self) -> Vec<usize> {
+        self.stderr.keys().cloned().collect()
+    }
+
+    /// Writes `rank-<N>.out` and `rank-<N>.err` for every rank with some output
+    /// and `mpirun.log` with the untagged lines
+    pub fn save(&self, dir: &Path) -> Fallible<()> {
+        fs::create_dir_all(dir).context(format!(
+            "creating the log directory {}",
+            dir.to_string_lossy()
+        ))?;
+
+        let logs = self
+            .stdout
+            .iter()
+            .map(|(rank, log)| (format!("rank-{}.out", rank), log))
+            .chain(
+                self.stderr
+                    .iter()
+                    .map(|(rank, log)| (format!("rank-{}.err", rank), log)),
+            );
+        for (name, log) in logs {
+            let path = dir.join(name);
+            fs::write(&path, log).context(format!("writing the log {}", path.to_string_lossy()))?;
+        }
+        if !self.untagged.is_empty() {
+            let path = dir.join("mpirun.log");
+            fs::write(&path, &self.untagged)
+                .context(format!("writing the log {}", path.to_string_lossy()))?;
+        }
+        Ok(())
+    }
+
+    /// Saves the logs and prints which ranks wrote to stderr
+    pub fn save_and_summarize(&self, dir: &Path) -> Fallible<()> {
+        self.save(dir)?;
+        info!("Per-rank logs written to {}", dir.to_string_lossy());
+
+        let ranks: Vec<_> = self
+            .stderr_ranks()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        if ranks.is_empty() {
+            println!("No rank wrote to stderr");
+        } else {
+            println!("Ranks which wrote to stderr: {}", ranks.join(", "));
+        }
+        Ok(())
+    }
+}
+
+/// Saves the per-rank logs of a failed execution, if the error contains its output
+pub fn save_from_error(e: &failure::Error, dir: &Path) {
+    let output = e
+        .iter_chain()
+        .filter_map(|cause| match cause.downcast_ref::<Error>() {
+            Some(Error::ExecutionError(output)) => Some(output),
+            _ => None,
+        })
+        .next();
+    if let Some(output) = output {
+        if let Err(e) = RankOutput::split(output).save_and_summarize(dir) {
+            warn!("Couldn't save the per-rank logs: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split() {
+        let output = "[1,0]<stdout>:hello from 0\n\
+                      [1,1]<stdout>:hello from 1\n\
+                      [1,1]<stderr>:error in 1\n\
+                      mpirun noticed that process rank 1 exited\n\
+                      [1,0]<stdout>:bye from 0\n";
+        let split = RankOutput::split(output);
+
+        assert_eq!(split.stdout[&0], "hello from 0\nbye from 0\n");
+        assert_eq!(split.stdout[&1], "hello from 1\n");
+        assert_eq!(split.stderr[&1], "error in 1\n");
+        assert_eq!(split.stderr_ranks(), vec![1]);
+        assert_eq!(
+            split.untagged,
+            "mpirun noticed that process rank 1 exited\n"
+        );
+    }
+}
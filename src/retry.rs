@@ -0,0 +1,257 @@
+use crate::{
+    check_cpus, checkpoint,
+    error::Error,
+    format_error_chain, is_interrupted,
+    jobconfig::{CheckpointConfig, FailureClass, RetryConfig},
+    session::mpi::SessionMPI,
+};
+use failure_ext::FutureExt;
+use futures::{
+    future::{self, Either, Loop},
+    prelude::*,
+};
+use gu_client::error::Error as GUError;
+use log::{error, info, warn};
+use serde_derive::Serialize;
+use std::{fmt, rc::Rc};
+
+/// The parameters of a single program execution
+#[derive(Clone, Debug)]
+pub struct ExecParams {
+    pub nproc: usize,
+    pub progname: String,
+    pub args: Vec<String>,
+    pub mpiargs: Vec<String>,
+    /// the directory containing the built binary, if the sources have been built
+    pub bindir: Option<String>,
+    /// the directory of the libraries of the prebuilt binary, see `mpirun_cmdline`
+    pub libdir: Option<String>,
+}
+
+/// A single attempt to execute the program
+#[derive(Debug, Serialize)]
+pub struct Attempt {
+    pub number: usize,
+    pub providers: Vec<String>,
+    pub error: Option<String>,
+    pub class: Option<FailureClass>,
+    /// providers excluded from the next attempts because of this failure
+    pub excluded: Vec<String>,
+}
+
+impl fmt::Display for Attempt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "attempt {} on {}: ",
+            self.number,
+            self.providers.join(", ")
+        )?;
+        match (&self.error, self.class) {
+            (None, _) => write!(f, "success"),
+            (Some(_), Some(class)) => write!(f, "{:?} failure", class),
+            (Some(_), None) => write!(f, "failure"),
+        }?;
+        if !self.excluded.is_empty() {
+            write!(f, ", excluded {}", self.excluded.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+/// Determines the kind of the failure of the program execution
+///
+/// Returns `None` for the failures which should never be retried,
+/// e.g. the user interrupting the execution.
+pub fn classify(e: &failure::Error) -> Option<FailureClass> {
+    if is_interrupted(e) {
+        return None;
+    }
+    for cause in e.iter_chain() {
+        if let Some(Error::ExecutionError(output)) = cause.downcast_ref::<Error>() {
+            return Some(classify_output(output));
+        }
+        if cause.downcast_ref::<GUError>().is_some() {
+            return Some(FailureClass::Hub);
+        }
+    }
+    None
+}
+
+/// Guesses the kind of the failure from the output of mpirun
+fn classify_output(output: &str) -> FailureClass {
+    const NETWORK_PATTERNS: &[&str] = &[
+        "unable to complete a tcp connection",
+        "btl_tcp",
+        "connection reset by peer",
+    ];
+    const NODE_PATTERNS: &[&str] = &[
+        "orte was unable to reliably start",
+        "orte has lost communication",
+        "an orte daemon has unexpectedly failed",
+        "ssh:",
+        "no route to host",
+        "host key verification failed",
+    ];
+
+    let output = output.to_lowercase();
+    if NETWORK_PATTERNS.iter().any(|p| output.contains(p)) {
+        FailureClass::Network
+    } else if NODE_PATTERNS.iter().any(|p| output.contains(p)) {
+        FailureClass::Node
+    } else {
+        FailureClass::Application
+    }
+}
+
+/// Executes the program, retrying according to the retry policy
+///
+/// If checkpointing is enabled, the checkpoints are pulled during the execution.
+/// After a failure, the next attempt starts from the latest checkpoint.
+///
+/// Returns: the execution output and the history of the attempts
+pub fn exec_with_retry(
+    session: Rc<SessionMPI>,
+    params: ExecParams,
+    policy: Option<RetryConfig>,
+    checkpoint_cfg: Option<CheckpointConfig>,
+) -> impl Future<Item = (String, Vec<Attempt>), Error = failure::Error> {
+    let max_attempts = policy.as_ref().map_or(1, |p| p.max_attempts);
+
+    future::loop_fn(vec![], move |mut history: Vec<Attempt>| {
+        let session = Rc::clone(&session);
+        let policy = policy.clone();
+        let checkpoint_cfg = checkpoint_cfg.clone();
+        let number = history.len() + 1;
+        let providers: Vec<_> = session
+            .active_nodes()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        if number > 1 {
+            info!("Execution attempt {}/{}", number, max_attempts);
+        }
+        let params = params.clone();
+        let nproc = params.nproc;
+
+        let exec = session
+            .exec(
+                nproc,
+                params.progname,
+                params.args,
+                params.mpiargs,
+                params.bindir,
+                params.libdir,
+            )
+            .context("program execution");
+
+        checkpoint::exec_with_checkpoints(exec, Rc::clone(&session), checkpoint_cfg.clone()).then(
+            move |res| {
+                let e = match res {
+                    Ok(output) => {
+                        history.push(Attempt {
+                            number,
+                            providers,
+                            error: None,
+                            class: None,
+                            excluded: vec![],
+                        });
+                        return Either::A(future::ok(Loop::Break((output, history))));
+                    }
+                    Err(e) => e,
+                };
+
+                let class = classify(&e);
+                let msg = format_error_chain(&e);
+                let retry = match (&policy, class) {
+                    (Some(policy), Some(class)) => {
+                        number < policy.max_attempts && policy.retry_on.contains(&class)
+                    }
+                    _ => false,
+                };
+                let exclude_failed = policy.as_ref().map_or(false, |p| p.exclude_failed);
+                let excluded = if retry && exclude_failed {
+                    session.providers_mentioned(&msg)
+                } else {
+                    vec![]
+                };
+                history.push(Attempt {
+                    number,
+                    providers,
+                    error: Some(msg),
+                    class,
+                    excluded: excluded.iter().map(ToString::to_string).collect(),
+                });
+
+                // Save the progress made before the failure, while the root provider
+                // is still in use
+                let pull = match &checkpoint_cfg {
+                    Some(cfg) => Either::A(checkpoint::pull_best_effort(&session, cfg)),
+                    None => Either::B(future::ok(())),
+                };
+
+                Either::B(pull.and_then(move |()| {
+                    if !retry {
+                        if history.len() > 1 {
+                            error!("All the execution attempts have failed:");
+                            for attempt in &history {
+                                error!("{}", attempt);
+                            }
+                        }
+                        return Either::A(future::err(e));
+                    }
+
+                    warn!(
+                        "Execution attempt {} has failed: {}",
+                        number,
+                        history[number - 1]
+                    );
+                    if exclude_failed && excluded.is_empty() {
+                        warn!("Couldn't determine the providers involved in the failure");
+                    }
+                    let root = session.active_nodes().first().cloned();
+                    session.exclude(&excluded);
+                    if let Err(e) = check_cpus(&session, nproc) {
+                        return Either::A(future::err(e));
+                    }
+
+                    // The inputs of the root provider have to follow it
+                    let reupload = if session.active_nodes().first() != root.as_ref() {
+                        Either::A(session.reupload_root_inputs())
+                    } else {
+                        Either::B(future::ok(()))
+                    };
+                    let push = match checkpoint_cfg {
+                        Some(cfg) => Either::A(checkpoint::push(&session, &cfg)),
+                        None => Either::B(future::ok(())),
+                    };
+                    Either::B(
+                        reupload
+                            .and_then(move |()| push)
+                            .map(move |()| Loop::Continue(history)),
+                    )
+                }))
+            },
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_output() {
+        let network = "A process or daemon was unable to complete a TCP connection\n\
+                       to another process:\n  Local host: node1\n  Remote host: 10.0.0.2";
+        assert_eq!(classify_output(network), FailureClass::Network);
+
+        let node = "ssh: connect to host 10.0.0.2 port 4222: No route to host\n\
+                    ORTE was unable to reliably start one or more daemons.";
+        assert_eq!(classify_output(node), FailureClass::Node);
+
+        let app = "Primary job terminated normally, but 1 process returned\n\
+                   a non-zero exit code.";
+        assert_eq!(classify_output(app), FailureClass::Application);
+    }
+}
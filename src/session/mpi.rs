@@ -1,10 +1,14 @@
 //use super::{Command, ProviderSession, ResourceFormat};
 use crate::{
-    error::Error,
-    jobconfig::{BuildType, OutputConfig, Sources},
+    archive::{self, ArchiveFormat, SourceArchive},
+    error::{CompilationFailure, Error},
+    jobconfig::{BinaryConfig, BuildOn, BuildType, InputScope, OutputConfig, OutputScope, Sources},
+    progress,
     session::gu_client_ext::PeerHardwareQuery,
+    toolchain::{self, Toolchain},
+    transfer::{self, to_hex, Digests, FileChunks},
 };
-use actix_web::{client, HttpMessage};
+use bytes::Bytes;
 use failure::{format_err, Fallible, ResultExt};
 use failure_ext::{FutureExt, OptionExt};
 use futures::{
@@ -24,10 +28,18 @@ use gu_client::{
 };
 use gu_hardware::actor::Hardware;
 use log::{debug, info, warn};
+use openssl::sha::Sha256;
+use serde_derive::{Deserialize, Serialize};
 use std::{
-    fs,
-    net::SocketAddr,
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt, fs, io, iter,
+    net::{IpAddr, SocketAddr},
     path::{Path, PathBuf},
+    process,
+    rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::{Duration, Instant},
 };
 
 #[derive(Debug)]
@@ -37,9 +49,74 @@ pub struct ProviderMPI {
     info: PeerInfo,
 }
 
+impl ProviderMPI {
+    fn ip(&self) -> IpAddr {
+        let ip_sock = &self.info.peer_addr;
+        let ip_sock: SocketAddr = ip_sock
+            .parse()
+            .unwrap_or_else(|_| panic!("GU returned an invalid IP address, {}", ip_sock));
+        ip_sock.ip()
+    }
+}
+
 pub struct SessionMPI {
     providers: Vec<ProviderMPI>,
+    /// Providers which are no longer used for running the application
+    excluded: RefCell<Vec<NodeId>>,
     hub_session: HubSession,
+    /// The input archives uploaded only to the root provider, with their destinations
+    root_inputs: RefCell<Vec<(PathBuf, String)>>,
+    /// The digests of the files transferred so far
+    digests: Digests,
+}
+
+/// The identifiers of a session, allowing to reconnect to it later
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionState {
+    hub_session: u64,
+    providers: Vec<ProviderState>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ProviderState {
+    session: String,
+    hardware: Hardware,
+    info: PeerInfo,
+}
+
+/// The state of a job started by `SessionMPI::exec_detached`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DetachedStatus {
+    Running,
+    Exited(i32),
+    /// The job is no longer running, but its exit code is unknown
+    Lost,
+}
+
+impl DetachedStatus {
+    fn parse(line: &str) -> Fallible<Self> {
+        let mut words = line.split_whitespace();
+        let status = match (words.next(), words.next()) {
+            (Some("running"), None) => DetachedStatus::Running,
+            (Some("lost"), None) => DetachedStatus::Lost,
+            (Some("exited"), Some(code)) => DetachedStatus::Exited(
+                code.parse()
+                    .context(format!("invalid exit code: {}", code))?,
+            ),
+            _ => return Err(format_err!("invalid job status: {}", line)),
+        };
+        Ok(status)
+    }
+}
+
+impl fmt::Display for DetachedStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            DetachedStatus::Running => write!(f, "running"),
+            DetachedStatus::Exited(code) => write!(f, "exited with code {}", code),
+            DetachedStatus::Lost => write!(f, "no longer running, exit code unknown"),
+        }
+    }
 }
 
 const GUMPI_IMAGE_NAME: &str = "marmistrz/gumpi";
@@ -49,17 +126,23 @@ const GUMPI_IMAGE_CHECKSUM: &str =
 const GUMPI_ENV_TYPE: &str = "docker";
 
 const GUMPI_DOCKER_USER: &str = "mpirun";
+const RUNUSER: &str = "runuser";
 
-const APP_SOURCES_PATH: &str = "/app";
-const APP_INPUT_PATH: &str = "/input";
+pub const APP_SOURCES_PATH: &str = "/app";
+/// the location of the shared libraries uploaded along with a prebuilt binary
+pub const APP_LIBRARY_PATH: &str = "/app/lib";
 const APP_WORKDIR: &str = "/output";
 
+// Bookkeeping of the jobs running in the background
+const DETACHED_CONTROL_PATH: &str = "/gumpi";
+const DETACHED_LOG_PATH: &str = "/gumpi/output.log";
+const DETACHED_EXIT_CODE_PATH: &str = "/gumpi/exit_code";
+
 impl SessionMPI {
-    pub fn init(
-        hub_ip: SocketAddr,
-        prov_filter: Option<Vec<NodeId>>,
-    ) -> impl Future<Item = SessionMPI, Error = failure::Error> {
-        println!("initializing gumpi");
+    /// Connects to the hub
+    ///
+    /// The connection may be shared between many sessions.
+    pub fn connect(hub_ip: SocketAddr) -> Fallible<HubConnection> {
         if hub_ip.ip().is_loopback() {
             warn!(
                 "The hub address {} is a loopback address. \
@@ -69,11 +152,16 @@ impl SessionMPI {
             );
         }
 
-        let hub_conn = HubConnection::from_addr(hub_ip.to_string()).context("invalid hub address");
-        let hub_conn = match hub_conn {
-            Err(e) => return Either::A(future::err(e.into())),
-            Ok(conn) => conn,
-        };
+        let hub_conn =
+            HubConnection::from_addr(hub_ip.to_string()).context("invalid hub address")?;
+        Ok(hub_conn)
+    }
+
+    pub fn init(
+        hub_conn: &HubConnection,
+        prov_filter: Option<Vec<NodeId>>,
+    ) -> impl Future<Item = SessionMPI, Error = failure::Error> {
+        println!("initializing gumpi");
 
         let hub_session = hub_conn.new_session(HubSessionSpec::default());
         let peers = hub_conn.list_peers();
@@ -104,8 +192,10 @@ impl SessionMPI {
             },
         };
 
-        Either::B(hub_session.join(peers).context("adding peers").and_then(
-            move |(session, peers)| {
+        hub_session
+            .join(peers)
+            .context("adding peers")
+            .and_then(move |(session, peers)| {
                 let hub_session = session.into_inner().unwrap();
                 let peers_session = hub_session.clone();
 
@@ -158,10 +248,56 @@ impl SessionMPI {
                         Ok(Self {
                             hub_session,
                             providers,
+                            excluded: RefCell::new(vec![]),
+                            root_inputs: RefCell::new(vec![]),
+                            digests: Digests::default(),
                         })
                     })
-            },
-        ))
+            })
+    }
+
+    /// Reconnects to a session saved by `SessionMPI::state`
+    pub fn restore(hub_conn: &HubConnection, state: SessionState) -> SessionMPI {
+        let hub_session = hub_conn.hub_session(state.hub_session);
+        let providers = state
+            .providers
+            .into_iter()
+            .map(|prov| ProviderMPI {
+                session: hub_session.peer(prov.info.node_id).session(prov.session),
+                hardware: prov.hardware,
+                info: prov.info,
+            })
+            .collect();
+
+        Self {
+            providers,
+            excluded: RefCell::new(vec![]),
+            hub_session,
+            root_inputs: RefCell::new(vec![]),
+            digests: Digests::default(),
+        }
+    }
+
+    /// Returns: the SHA-256 digests of the files transferred so far, by their descriptions
+    pub fn digests(&self) -> BTreeMap<String, String> {
+        self.digests.borrow().clone()
+    }
+
+    pub fn state(&self) -> SessionState {
+        let providers = self
+            .providers
+            .iter()
+            .map(|prov| ProviderState {
+                session: prov.session.id().to_owned(),
+                hardware: prov.hardware.clone(),
+                info: prov.info.clone(),
+            })
+            .collect();
+
+        SessionState {
+            hub_session: self.hub_session.id(),
+            providers,
+        }
     }
 
     pub fn close(&self) -> impl Future<Item = (), Error = GUError> {
@@ -172,19 +308,53 @@ impl SessionMPI {
     }
 
     fn root_provider(&self) -> &ProviderMPI {
-        self.providers.first().expect("no providers")
+        self.active_providers()
+            .into_iter()
+            .next()
+            .expect("no providers")
+    }
+
+    /// Returns: the providers which haven't been excluded
+    fn active_providers(&self) -> Vec<&ProviderMPI> {
+        let excluded = self.excluded.borrow();
+        self.providers
+            .iter()
+            .filter(|p| !excluded.contains(&p.info.node_id))
+            .collect()
+    }
+
+    pub fn active_nodes(&self) -> Vec<NodeId> {
+        self.active_providers()
+            .iter()
+            .map(|p| p.info.node_id)
+            .collect()
+    }
+
+    /// Stops using the given providers for running the application
+    ///
+    /// The hostfile and the root provider are chosen from the remaining providers.
+    pub fn exclude(&self, nodes: &[NodeId]) {
+        for node in nodes {
+            info!("Excluding provider {}", node.to_string());
+        }
+        self.excluded.borrow_mut().extend_from_slice(nodes);
+    }
+
+    /// Returns: the active providers whose IP address is mentioned in the text
+    pub fn providers_mentioned(&self, text: &str) -> Vec<NodeId> {
+        self.active_providers()
+            .iter()
+            .filter(|p| mentions_ip(text, &p.ip().to_string()))
+            .map(|p| p.info.node_id)
+            .collect()
     }
 
     pub fn hostfile(&self) -> String {
-        let peers = &self.providers;
+        let peers = self.active_providers();
         let file_lines: Vec<_> = peers
             .iter()
             .map(|peer| {
-                let ip_sock = &peer.info.peer_addr;
-                let ip_sock: SocketAddr = ip_sock
-                    .parse()
-                    .unwrap_or_else(|_| panic!("GU returned an invalid IP address, {}", ip_sock));
-                let ip = ip_sock.ip();
+                let ip = peer.ip();
                 let cpus = peer.hardware.num_cores();
 
                 format!("{} port=4222 slots={}", ip, cpus)
@@ -194,7 +364,10 @@ impl SessionMPI {
     }
 
     pub fn total_cpus(&self) -> usize {
-        self.providers.iter().map(|p| p.hardware.num_cores()).sum()
+        self.active_providers()
+            .iter()
+            .map(|p| p.hardware.num_cores())
+            .sum()
     }
 
     pub fn exec(
@@ -203,69 +376,21 @@ impl SessionMPI {
         progname: String,
         args: Vec<String>,
         mpiargs: Vec<String>,
-        deployed: bool,
+        bindir: Option<String>,
+        libdir: Option<String>,
     ) -> impl Future<Item = String, Error = failure::Error> {
         let root = self.root_provider();
-
-        // Step 1: prepare the command line.
-        //
-        // We execute the program on the root provider in the following manner:
-        //      runuser -u mpirun -- mpirun /path/to/executable arg1 arg2
-
-        let mut cmdline = vec![];
-
-        // We use runuser to make sure we're not running as root
-        let executable = "runuser".to_owned();
-
-        let runuser_args = vec!["-u", GUMPI_DOCKER_USER, "--"]
-            .into_iter()
-            .map(ToOwned::to_owned);
-        cmdline.extend(runuser_args);
-
-        // ... to call mpirun, first adding gumpi-logic arguments, later the
-        // custom user defined arguments
-        cmdline.push("mpirun".to_owned());
-        cmdline.extend(vec![
-            "-n".to_owned(),
-            nproc.to_string(),
-            "--hostfile".to_owned(),
-            "/hostfile".to_owned(),
-        ]);
-        cmdline.extend(mpiargs);
-
-        // ... then the program name ...
-        //
-        // If we've built the sources, we need to give the exact path to the binary
-        // Otherwise it's somewhere on the system, so let the user decide
-        let progname = if deployed {
-            format!("{}/{}", APP_SOURCES_PATH, progname)
-        } else {
-            progname
-        };
-        cmdline.push(progname);
-
-        // Finally the user-defined applicadtion arguments
-        cmdline.extend(args);
-        let cmdline = cmdline;
+        let cmdline = mpirun_cmdline(nproc, progname, args, mpiargs, bindir, libdir);
         info!("Executing mpirun with args {:?}...", cmdline);
 
-        // Step 2: upload the hostfile and execute the command
-        let hostfile = self.hostfile();
-        info!("HOSTFILE:\n{}", hostfile);
-
-        let upload_cmd = Command::WriteFile {
-            content: hostfile,
-            file_path: "hostfile".to_owned(),
-        };
-
         let exec_cmd = Command::Exec {
-            executable,
+            executable: RUNUSER.to_owned(),
             args: cmdline,
             working_dir: APP_WORKDIR.to_owned().into(),
         };
 
         root.session
-            .update(vec![upload_cmd, exec_cmd])
+            .update(vec![self.hostfile_cmd(), exec_cmd])
             .map_err(|e| match e {
                 GUError::ProcessingResult(mut outs) => {
                     assert_eq!(outs.len(), 2);
@@ -290,8 +415,138 @@ impl SessionMPI {
             })
     }
 
+    /// Starts the program in the background, without waiting for it to finish
+    ///
+    /// The output of mpirun is redirected to a log file on the root provider
+    /// and the exit code is saved once mpirun terminates.
+    ///
+    /// Returns: the PID of the process group running mpirun on the root provider
+    pub fn exec_detached(
+        &self,
+        nproc: usize,
+        progname: String,
+        args: Vec<String>,
+        mpiargs: Vec<String>,
+        bindir: Option<String>,
+        libdir: Option<String>,
+    ) -> impl Future<Item = u32, Error = failure::Error> {
+        let root = self.root_provider();
+        let cmdline = mpirun_cmdline(nproc, progname, args, mpiargs, bindir, libdir);
+        info!(
+            "Starting mpirun in the background with args {:?}...",
+            cmdline
+        );
+
+        let mut quoted = vec![RUNUSER.to_owned()];
+        quoted.extend(cmdline);
+        let quoted: Vec<_> = quoted.iter().map(|arg| shell_quote(arg)).collect();
+        let job_script = format!(
+            "{} > {} 2>&1; echo $? > {}",
+            quoted.join(" "),
+            DETACHED_LOG_PATH,
+            DETACHED_EXIT_CODE_PATH
+        );
+        // setsid makes the job a leader of a new process group,
+        // so that it can be cancelled as a whole
+        let start_script = format!(
+            "mkdir -p {}; rm -f {}; setsid sh -c {} > /dev/null 2>&1 < /dev/null & echo $!",
+            DETACHED_CONTROL_PATH,
+            DETACHED_EXIT_CODE_PATH,
+            shell_quote(&job_script)
+        );
+
+        let exec_cmd = Command::Exec {
+            executable: "sh".to_owned(),
+            args: vec!["-c".to_owned(), start_script],
+            working_dir: APP_WORKDIR.to_owned().into(),
+        };
+
+        root.session
+            .update(vec![self.hostfile_cmd(), exec_cmd])
+            .from_err()
+            .and_then(|outs| {
+                let out = outs.last().map(|s| s.trim()).unwrap_or_default();
+                out.parse::<u32>()
+                    .context(format!("invalid PID of the started job: {}", out))
+                    .map_err(Into::into)
+            })
+    }
+
+    /// Checks the state of a job started by `exec_detached`
+    ///
+    /// If `log_offset` is given, the output written by the job
+    /// starting from this byte is returned as well, as raw bytes.
+    pub fn poll_detached(
+        &self,
+        pid: u32,
+        log_offset: Option<u64>,
+    ) -> impl Future<Item = (DetachedStatus, Vec<u8>), Error = failure::Error> {
+        let mut script = format!(
+            "if [ -f {exit} ]; then echo exited $(cat {exit}); \
+             elif kill -0 {pid} 2> /dev/null; then echo running; \
+             else echo lost; fi",
+            exit = DETACHED_EXIT_CODE_PATH,
+            pid = pid
+        );
+        if let Some(offset) = log_offset {
+            // tail counts the bytes from 1. The output is dumped in hex, since GU
+            // decodes it as UTF-8, which would garble the characters split by tail.
+            script.push_str(&format!(
+                "; tail -c +{} {} 2> /dev/null | od -An -v -tx1 || true",
+                offset + 1,
+                DETACHED_LOG_PATH
+            ));
+        }
+
+        let exec_cmd = Command::Exec {
+            executable: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script],
+            working_dir: APP_WORKDIR.to_owned().into(),
+        };
+
+        self.root_provider()
+            .session
+            .update(vec![exec_cmd])
+            .from_err()
+            .and_then(|mut outs| {
+                let out = outs.pop().unwrap_or_default();
+                let (status_line, log) = match out.find('\n') {
+                    Some(idx) => (&out[..idx], &out[idx + 1..]),
+                    None => (&out[..], ""),
+                };
+                let status = DetachedStatus::parse(status_line)?;
+                Ok((status, parse_hex_dump(log)?))
+            })
+    }
+
+    /// Terminates the whole process group of a job started by `exec_detached`
+    pub fn cancel_detached(&self, pid: u32) -> impl Future<Item = (), Error = failure::Error> {
+        let script = format!("kill -s TERM -- -{} 2> /dev/null || true", pid);
+        let exec_cmd = Command::Exec {
+            executable: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script],
+            working_dir: APP_WORKDIR.to_owned().into(),
+        };
+
+        self.root_provider()
+            .session
+            .update(vec![exec_cmd])
+            .from_err()
+            .map(|_| ())
+    }
+
+    fn hostfile_cmd(&self) -> Command {
+        let hostfile = self.hostfile();
+        info!("HOSTFILE:\n{}", hostfile);
+
+        Command::WriteFile {
+            content: hostfile,
+            file_path: "hostfile".to_owned(),
+        }
+    }
+
     fn get_deployments(&self) -> Vec<PeerSession> {
-        self.providers
+        self.active_providers()
             .iter()
             .map(|provider| provider.session.clone())
             .collect()
@@ -310,61 +565,179 @@ impl SessionMPI {
         config_path: PathBuf,
         sources: Sources,
     ) -> impl Future<Item = DeploymentInfo, Error = failure::Error> {
-        let tarball_path = config_path.join(&sources.path);
+        let hub_session = self.hub_session.clone();
+        let digests = Rc::clone(&self.digests);
+        let providers: Vec<_> = self
+            .active_providers()
+            .iter()
+            .map(|provider| (provider.session.clone(), provider.hardware.num_cores()))
+            .collect();
+        let arches = match sources.build_on {
+            BuildOn::Platform => Either::A(self.probe_arches()),
+            BuildOn::Node => Either::B(future::ok(BTreeMap::new())),
+        };
 
-        let deployments: Vec<_> = self.get_deployments();
+        let prepared = archive::pack_sources(&config_path, &sources)
+            .context("preparing the sources")
+            .map_err(failure::Error::from)
+            .and_then(|packed| {
+                let hash = sources_hash(&packed.0, &sources)?;
+                Ok((packed, hash))
+            });
 
-        self.upload_to_hub(&tarball_path)
-            .context("uploading the source tarball")
-            .and_then(move |blob| {
-                let cmds = generate_deployment_cmds(blob, sources.mode);
-                info!("Building the application on provider nodes");
-                debug!("Executing the following build commands: {:#?}", cmds);
-                let build_futs = deployments
+        prepared
+            .into_future()
+            .join(arches)
+            .and_then(move |((packed, hash), arches)| {
+                let targets = providers.into_iter().map(|(session, cores)| BuildTarget {
+                    platform: arches.get(&session.node_id().to_string()).cloned(),
+                    session,
+                    cores,
+                });
+                let checks: Vec<_> = targets
                     .into_iter()
-                    .map(move |session| {
-                        let node = session.node_id();
-                        session
-                            .update(cmds.clone())
-                            .map_err(|e| -> failure::Error {
-                                match e {
-                                    GUError::ProcessingResult(outs) => {
-                                        Error::CompilationError(outs).into()
-                                    }
-                                    x => x.into(),
-                                }
-                            })
-                            .context(format!("compiling the app on node {}", node.to_string()))
-                            .and_then(move |logs| Ok(CompilationInfo { logs, node }))
+                    .map(|target| {
+                        has_build(&target.session, &hash).map(move |built| (target, built))
                     })
-                    .collect::<Vec<_>>();
+                    .collect();
+                future::join_all(checks).map(move |checked| (packed, hash, checked))
+            })
+            .and_then(move |(packed, hash, checked)| {
+                let (built, to_build): (Vec<_>, Vec<_>) =
+                    checked.into_iter().partition(|(_, built)| *built);
+                let skipped: Vec<_> = built
+                    .into_iter()
+                    .map(|(target, _)| CompilationInfo {
+                        node: target.session.node_id(),
+                        logs: vec!["The sources haven't changed, skipping the build".to_owned()],
+                        duration: Duration::from_secs(0),
+                    })
+                    .collect();
+                let to_build: Vec<_> = to_build.into_iter().map(|(target, _)| target).collect();
 
-                future::join_all(build_futs).and_then(|logs| Ok(DeploymentInfo { logs }))
+                if to_build.is_empty() {
+                    info!("The application is up to date on all the provider nodes");
+                    return Either::A(future::ok(DeploymentInfo { logs: skipped }));
+                }
+                let build = build_sources(hub_session, packed, sources, hash, to_build, digests);
+                Either::B(build.map(move |mut info| {
+                    info.logs.extend(skipped);
+                    info
+                }))
             })
     }
 
-    fn upload_to_hub(&self, file_path: &Path) -> impl Future<Item = Blob, Error = GUError> {
-        let fname = file_path.to_string_lossy().into_owned();
-        let file = fs::read(file_path).map(Into::into);
-        let file_stream = futures::stream::once(file);
-        self.hub_session
-            .new_blob()
-            .from_err()
-            .and_then(move |blob| {
-                info!("Uploading the file {} to the hub", fname);
-                blob.upload_from_stream(file_stream)
-                    .and_then(move |_| Ok(blob))
-            })
+    /// Returns: the architectures of the active providers reported by `uname -m`,
+    /// by the node ids
+    ///
+    /// The providers which couldn't be probed are missing.
+    fn probe_arches(&self) -> impl Future<Item = BTreeMap<String, String>, Error = failure::Error> {
+        let cmd = Command::Exec {
+            executable: "uname".to_owned(),
+            args: vec!["-m".to_owned()],
+            working_dir: APP_WORKDIR.to_owned().into(),
+        };
+        let deployments = self.get_deployments();
+
+        future::lazy(move || {
+            let futures = deployments.into_iter().map(move |session| {
+                let node = session.node_id().to_string();
+                session.update(vec![cmd.clone()]).then(move |res| {
+                    let arch = match res {
+                        Ok(outs) => outs.last().map(|out| out.trim().to_owned()),
+                        Err(e) => {
+                            warn!(
+                                "Couldn't probe the architecture of node {}, \
+                                 it will build the application on its own: {}",
+                                node, e
+                            );
+                            None
+                        }
+                    };
+                    let arch = arch.filter(|arch| !arch.is_empty());
+                    Ok::<_, failure::Error>(arch.map(|arch| (node, arch)))
+                })
+            });
+            future::join_all(futures)
+        })
+        .map(|arches| arches.into_iter().flatten().collect())
+    }
+
+    fn upload_to_hub(
+        &self,
+        file_path: &Path,
+    ) -> impl Future<Item = (Blob, String), Error = failure::Error> {
+        upload_to_hub(&self.hub_session, file_path)
     }
 
+    /// Uploads the input archive and unpacks it to `destination` on the providers
+    /// in the `scope`
     pub fn upload_input(
         &self,
         input_tarball: PathBuf,
-    ) -> impl Future<Item = (), Error = failure::Error> {
-        let deployments = self.get_deployments();
-        let name = input_tarball
+        destination: String,
+        scope: InputScope,
+    ) -> impl Future<Item = Vec<(NodeId, Duration)>, Error = failure::Error> {
+        let deployments = match scope {
+            InputScope::All => self.get_deployments(),
+            InputScope::Root => {
+                self.root_inputs
+                    .borrow_mut()
+                    .push((input_tarball.clone(), destination.clone()));
+                vec![self.root_provider().session.clone()]
+            }
+        };
+        let context = format!("uploading the input {}", input_tarball.to_string_lossy());
+        let format = ArchiveFormat::from_path(&input_tarball);
+        self.upload_archive_to(deployments, input_tarball, format, destination)
+            .context(context)
+    }
+
+    /// Uploads the inputs with `InputScope::Root` to the current root provider
+    ///
+    /// Needed when the previous root provider has been excluded.
+    pub fn reupload_root_inputs(&self) -> impl Future<Item = (), Error = failure::Error> {
+        let root = vec![self.root_provider().session.clone()];
+        let uploads: Vec<_> = self
+            .root_inputs
+            .borrow()
+            .iter()
+            .map(|(tarball, destination)| {
+                let context = format!(
+                    "uploading the input {} to the new root provider",
+                    tarball.to_string_lossy()
+                );
+                let format = ArchiveFormat::from_path(tarball);
+                self.upload_archive_to(root.clone(), tarball.clone(), format, destination.clone())
+                    .context(context)
+            })
+            .collect();
+        future::join_all(uploads).map(|_| ())
+    }
+
+    /// Uploads the archive in the given format and unpacks it to `destination`
+    /// on every active provider
+    ///
+    /// Returns: the time of the download on every provider
+    pub fn upload_archive(
+        &self,
+        tarball: PathBuf,
+        format: ArchiveFormat,
+        destination: String,
+    ) -> impl Future<Item = Vec<(NodeId, Duration)>, Error = failure::Error> {
+        self.upload_archive_to(self.get_deployments(), tarball, format, destination)
+    }
+
+    fn upload_archive_to(
+        &self,
+        deployments: Vec<PeerSession>,
+        tarball: PathBuf,
+        format: ArchiveFormat,
+        destination: String,
+    ) -> impl Future<Item = Vec<(NodeId, Duration)>, Error = failure::Error> {
+        let name = tarball
             .file_name()
-            .ok_or_else(|| format_err!("input_tarball is not a file"))
+            .ok_or_else(|| format_err!("{} is not a file", tarball.to_string_lossy()))
             .and_then(|s| {
                 s.to_str()
                     .ok_or_context("invalid UTF-8")
@@ -372,79 +745,321 @@ impl SessionMPI {
                     .map_err(Into::into)
             });
 
-        self.upload_to_hub(&input_tarball)
-            .context("uploading input data")
+        let digests = Rc::clone(&self.digests);
+        self.upload_to_hub(&tarball)
+            .context("uploading the archive to the hub")
             .join(name.into_future())
-            .and_then(move |(blob, name)| {
-                info!("Downloading the input data, {}", name);
+            .and_then(move |((blob, digest), name)| {
+                info!("Downloading {} to {}", name, destination);
+                digests.borrow_mut().insert(
+                    format!("{} (upload)", tarball.to_string_lossy()),
+                    digest.clone(),
+                );
 
-                let download_cmd = Command::DownloadFile {
-                    format: ResourceFormat::Tar,
-                    uri: blob.uri(),
-                    file_path: APP_INPUT_PATH.to_owned(),
-                };
-                let futures = deployments
-                    .into_iter()
-                    .map(move |session| session.update(vec![download_cmd.clone()]).from_err());
-                future::join_all(futures)
+                let download_cmds = download_cmds(blob.uri(), format, &destination, &digest);
+                update_all(deployments, download_cmds, format!("Downloading {}", name))
+            })
+    }
+
+    /// Uploads the prebuilt binary and its libraries to `/app` on every active provider
+    ///
+    /// The binary is saved as `progname`, the libraries go to `APP_LIBRARY_PATH`.
+    ///
+    /// Returns: the time of the download on every provider
+    pub fn deploy_binary(
+        &self,
+        config_path: &Path,
+        binary: &BinaryConfig,
+        progname: &str,
+    ) -> impl Future<Item = Vec<(NodeId, Duration)>, Error = failure::Error> {
+        let deployments = self.get_deployments();
+        let hub_session = self.hub_session.clone();
+        let digests = Rc::clone(&self.digests);
+        let name = format!("the binary {}", binary.path.to_string_lossy());
+        // GU may not preserve the permissions when unpacking the tarball
+        let chmod_cmd = Command::Exec {
+            executable: "chmod".to_owned(),
+            args: vec![
+                "+x".to_owned(),
+                format!("{}/{}", APP_SOURCES_PATH, progname),
+            ],
+            working_dir: APP_SOURCES_PATH.to_owned().into(),
+        };
+
+        archive::pack_binary(config_path, binary, progname)
+            .context("packing the binary")
+            .map_err(failure::Error::from)
+            .into_future()
+            .and_then(move |archive| {
+                upload_data_to_hub(&hub_session, name.clone(), archive)
+                    .context("uploading the binary to the hub")
+                    .map(move |uploaded| (uploaded, name))
             })
-            .and_then(|_| Ok(()))
+            .and_then(move |((blob, digest), name)| {
+                info!("Downloading the binary to {}", APP_SOURCES_PATH);
+                digests
+                    .borrow_mut()
+                    .insert(format!("{} (upload)", name), digest.clone());
+                let mut cmds =
+                    download_cmds(blob.uri(), ArchiveFormat::Tar, APP_SOURCES_PATH, &digest);
+                cmds.push(chmod_cmd);
+                update_all(deployments, cmds, "Downloading the binary".to_owned())
+            })
+    }
+
+    /// Runs `toolchain::PROBE_SCRIPT` on every provider
+    ///
+    /// Returns: the result of the probe on every provider, so that a failure on one of them
+    /// doesn't discard the others
+    pub fn probe_toolchains(
+        &self,
+    ) -> impl Future<Item = Vec<Fallible<Toolchain>>, Error = failure::Error> {
+        let cmd = Command::Exec {
+            executable: "sh".to_owned(),
+            args: vec!["-c".to_owned(), toolchain::PROBE_SCRIPT.to_owned()],
+            working_dir: APP_WORKDIR.to_owned().into(),
+        };
+        let deployments = self.get_deployments();
+
+        future::lazy(move || {
+            let futures = deployments.into_iter().map(move |session| {
+                let node = session.node_id().to_string();
+                session
+                    .update(vec![cmd.clone()])
+                    .context(format!("probing the toolchain on node {}", node))
+                    .map(move |outs| {
+                        let out = outs.last().map_or("", String::as_str);
+                        Toolchain::parse(node, out)
+                    })
+                    .map_err(failure::Error::from)
+                    .then(Ok::<_, failure::Error>)
+            });
+            future::join_all(futures)
+        })
     }
 
+    /// Checks that the program can be run on every provider, as the MPI user
+    ///
+    /// With `bindir`, the program has to be an executable file in it.
+    /// Otherwise, it's looked up on the PATH.
+    pub fn check_binary(
+        &self,
+        progname: &str,
+        bindir: Option<&str>,
+    ) -> impl Future<Item = (), Error = failure::Error> {
+        let deployments = self.get_deployments();
+        let (script, program) = match bindir {
+            Some(dir) => (FILE_CHECK_SCRIPT, format!("{}/{}", dir, progname)),
+            None => (PATH_CHECK_SCRIPT, progname.to_owned()),
+        };
+        let args = vec!["-u", GUMPI_DOCKER_USER, "--", "sh", "-c", script, "sh"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .chain(iter::once(program.clone()))
+            .collect();
+        let cmd = Command::Exec {
+            executable: RUNUSER.to_owned(),
+            args,
+            working_dir: APP_WORKDIR.to_owned().into(),
+        };
+
+        future::lazy(move || {
+            let futures = deployments.into_iter().map(move |session| {
+                let node = session.node_id();
+                session
+                    .update(vec![cmd.clone()])
+                    .context(format!("checking the program on node {}", node.to_string()))
+                    .map(move |outs| {
+                        let out = outs.last().map_or("", |out| out.trim());
+                        if out == "OK" {
+                            None
+                        } else {
+                            Some((node, out.to_owned()))
+                        }
+                    })
+            });
+            future::join_all(futures)
+        })
+        .and_then(move |results| {
+            let missing: Vec<_> = results
+                .into_iter()
+                .flatten()
+                .map(|(node, out)| match path_searched(&out) {
+                    Some(path) => format!("node {}: not found in PATH={}", node.to_string(), path),
+                    None => format!(
+                        "node {}: {} doesn't exist or isn't executable",
+                        node.to_string(),
+                        program
+                    ),
+                })
+                .collect();
+            if missing.is_empty() {
+                Ok(())
+            } else {
+                Err(format_err!(
+                    "the program is missing on {} node(s):\n{}",
+                    missing.len(),
+                    missing.join("\n")
+                ))
+            }
+        })
+    }
+
+    /// Downloads the output directory, in the format given in the config
+    /// or implied by the name of the target file
+    ///
+    /// With `OutputScope::All`, the archive of every provider is saved
+    /// to `<target>/<node id>.<extension>`. The archives are then extracted, if configured.
     pub fn retrieve_output(
         &self,
         output_cfg: &OutputConfig,
     ) -> impl Future<Item = (), Error = failure::Error> {
-        let path = output_cfg
-            .source
+        let format = output_cfg.format();
+        let target = output_cfg.target.clone();
+        let max_size = output_cfg.max_size;
+
+        let sessions = match output_cfg.scope {
+            OutputScope::Root => vec![(None, self.root_provider().session.clone())],
+            OutputScope::All => self
+                .get_deployments()
+                .into_iter()
+                .map(|session| (Some(session.node_id().to_string()), session))
+                .collect(),
+        };
+        let archives: Vec<_> = sessions
+            .into_iter()
+            .map(|(node, session)| {
+                let archive = match &node {
+                    Some(node) => target.join(format!("{}.{}", node, format.extension())),
+                    None => target.clone(),
+                };
+                (node, session, archive)
+            })
+            .collect();
+
+        let created = match output_cfg.scope {
+            OutputScope::All => fs::create_dir_all(&target).context(format!(
+                "creating the output directory {}",
+                target.to_string_lossy()
+            )),
+            OutputScope::Root => Ok(()),
+        };
+        let downloads: Vec<_> = archives
+            .into_iter()
+            .map(|(node, session, archive)| {
+                let transfer = ArchiveTransfer {
+                    format,
+                    max_size,
+                    filter: output_cfg.filter.clone(),
+                };
+                self.retrieve_archive_from(session, &output_cfg.source, archive.clone(), transfer)
+                    .map(move |()| (node, archive))
+            })
+            .collect();
+
+        let extract = output_cfg.extract.clone();
+        let merge = output_cfg.merge;
+        let on_conflict = output_cfg.on_conflict;
+        created
+            .map_err(failure::Error::from)
+            .into_future()
+            .and_then(move |()| future::join_all(downloads))
+            .and_then(move |archives| -> Fallible<()> {
+                let dir = match extract {
+                    Some(dir) => dir,
+                    None => return Ok(()),
+                };
+                // The archives are extracted one by one, so the merged files
+                // are checked for the conflicts
+                for (node, archive) in archives {
+                    let dest = match &node {
+                        Some(node) if !merge => dir.join(node),
+                        _ => dir.clone(),
+                    };
+                    let extracted = archive::extract(&archive, format, &dest, on_conflict)
+                        .context(format!("extracting {}", archive.to_string_lossy()))?;
+                    info!(
+                        "Extracted {} files to {}, skipped {}",
+                        extracted.files,
+                        dest.to_string_lossy(),
+                        extracted.skipped
+                    );
+                }
+                Ok(())
+            })
+    }
+
+    /// Downloads the directory from the root provider as an archive in the given format
+    pub fn retrieve_dir(
+        &self,
+        source: &Path,
+        target: PathBuf,
+        format: ArchiveFormat,
+    ) -> impl Future<Item = (), Error = failure::Error> {
+        let transfer = ArchiveTransfer {
+            format,
+            max_size: None,
+            filter: FileFilter::default(),
+        };
+        let root_session = self.root_provider().session.clone();
+        self.retrieve_archive_from(root_session, source, target, transfer)
+    }
+
+    /// Downloads the directory from the given provider as an archive
+    ///
+    /// The compressed archives are created on the provider, to save the transfer.
+    fn retrieve_archive_from(
+        &self,
+        session: PeerSession,
+        source: &Path,
+        target: PathBuf,
+        transfer: ArchiveTransfer,
+    ) -> impl Future<Item = (), Error = failure::Error> {
+        let ArchiveTransfer {
+            format,
+            max_size,
+            filter,
+        } = transfer;
+        let path = source
             .to_str()
             .ok_or_context("output_path is not valid unicode")
             .map(str::to_owned)
             .into_future()
             .map_err(failure::Error::from);
-        let output_file = output_cfg.target.clone();
         let blob = self.hub_session.new_blob().from_err();
-        let root_session = self.root_provider().session.clone();
+        let node = session.node_id().to_string();
+        let digests = Rc::clone(&self.digests);
 
         blob.join(path)
             .and_then(move |(blob, path)| {
-                info!("Uploading the outputs from the provider to the hub");
-                let cmd = Command::UploadFile {
-                    file_path: path,
-                    format: ResourceFormat::Tar,
-                    uri: blob.uri(),
-                };
-                root_session
-                    .update(vec![cmd])
-                    .context("uploading the outputs from the provider to the hub")
-                    .and_then(|_| future::ok(blob))
-            })
-            .and_then(|blob| {
-                info!("Downloading the outputs from the hub");
-                client::ClientRequest::get(blob.uri())
-                    .finish()
-                    .unwrap()
-                    .send()
-                    .context("Downloading the outputs from the hub")
-                    .and_then(|response| {
-                        let status = response.status();
-                        if status.is_success() {
-                            Either::A(response.body().limit(1024 * 1024 * 1024).from_err())
-                        // 1 GiB limit
-                        } else {
-                            let err = format_err!("Error downloading the outputs: {}", status);
-                            Either::B(future::err(err))
-                        }
+                let phase = format!("Uploading {} to the hub", path);
+                let progress = Rc::new(progress::Nodes::new(phase, iter::once(node.clone())));
+                let update = session.update(upload_cmds(blob.uri(), format, &path, &filter));
+                tracked(update, progress, node.clone(), "packing and uploading")
+                    .context(format!(
+                        "uploading the directory from the provider {} to the hub",
+                        node
+                    ))
+                    .and_then(|outs| -> Fallible<_> {
+                        // The output of sha256sum is `<digest>  <file>`
+                        let digest = outs
+                            .get(1)
+                            .and_then(|out| out.split_whitespace().next())
+                            .ok_or_context("no checksum of the archive")?;
+                        Ok((blob, digest.to_owned()))
                     })
             })
-            .and_then(|body| {
+            .and_then(move |(blob, digest)| {
                 info!(
-                    "Writing the application outputs to {}",
-                    output_file.to_string_lossy()
+                    "Downloading the directory from the hub to {}",
+                    target.to_string_lossy()
                 );
-                fs::write(output_file, body)
-                    .context("Writing the outputs")
-                    .map_err(Into::into)
+                digests.borrow_mut().insert(
+                    format!("{} (download)", target.to_string_lossy()),
+                    digest.clone(),
+                );
+                transfer::download_to_file(blob.uri(), target, max_size, Some(digest))
+                    .context("downloading the directory from the hub")
             })
     }
 
@@ -496,48 +1111,876 @@ pub struct DeploymentInfo {
 pub struct CompilationInfo {
     pub node: NodeId,
     pub logs: Vec<String>,
+    pub duration: Duration,
+}
+
+/// Streams the file to a new blob on the hub
+///
+/// Returns: the blob and the SHA-256 digest of the file
+fn upload_to_hub(
+    hub_session: &HubSession,
+    file_path: &Path,
+) -> impl Future<Item = (Blob, String), Error = failure::Error> {
+    let hub_session = hub_session.clone();
+    let fname = file_path.to_string_lossy().into_owned();
+    FileChunks::open(file_path)
+        .context(format!("opening {}", fname))
+        .map_err(failure::Error::from)
+        .into_future()
+        .and_then(move |chunks| upload_stream_to_hub(&hub_session, fname, chunks))
+}
+
+/// Uploads the data prepared in memory, `name` is only used for the logs
+///
+/// Returns: the blob and the SHA-256 digest of the data
+fn upload_data_to_hub(
+    hub_session: &HubSession,
+    name: String,
+    data: Vec<u8>,
+) -> impl Future<Item = (Blob, String), Error = failure::Error> {
+    let chunks = transfer::data_chunks(data, &name);
+    upload_stream_to_hub(hub_session, name, chunks)
+}
+
+/// Uploads the chunks to a new blob on the hub, `name` is only used for the logs
+///
+/// Returns: the blob and the SHA-256 digest of the chunks
+fn upload_stream_to_hub<S>(
+    hub_session: &HubSession,
+    name: String,
+    chunks: S,
+) -> impl Future<Item = (Blob, String), Error = failure::Error>
+where
+    S: Stream<Item = Bytes, Error = io::Error> + 'static,
+{
+    let (chunks, hasher) = transfer::hashed(chunks);
+    hub_session.new_blob().from_err().and_then(move |blob| {
+        info!("Uploading {} to the hub", name);
+        blob.upload_from_stream(chunks)
+            .from_err()
+            .and_then(move |_| {
+                let digest = to_hex(&hasher.replace(Sha256::new()).finish());
+                debug!("SHA-256 of {}: {}", name, digest);
+                Ok((blob, digest))
+            })
+    })
+}
+
+/// Prints `OK` if `$1` is an executable file
+const FILE_CHECK_SCRIPT: &str =
+    r#"if [ -f "$1" ] && [ -x "$1" ]; then echo OK; else echo MISSING; fi"#;
+
+/// Prints `OK` if `$1` is found on the PATH, otherwise the PATH searched
+const PATH_CHECK_SCRIPT: &str =
+    r#"if command -v "$1" >/dev/null 2>&1; then echo OK; else echo "MISSING $PATH"; fi"#;
+
+/// Returns: the PATH searched, from the output of `PATH_CHECK_SCRIPT`
+fn path_searched(out: &str) -> Option<&str> {
+    if out.starts_with("MISSING ") {
+        Some(&out["MISSING ".len()..])
+    } else {
+        None
+    }
 }
 
-fn generate_deployment_cmds(blob: Blob, mode: BuildType) -> Vec<Command> {
-    let download_cmd = Command::DownloadFile {
-        format: ResourceFormat::Tar,
-        uri: blob.uri(),
-        file_path: APP_SOURCES_PATH.to_owned(),
+/// A provider the application is deployed to
+struct BuildTarget {
+    session: PeerSession,
+    cores: usize,
+    /// the architecture, if known
+    platform: Option<String>,
+}
+
+/// The file next to the built application, holding the hash of its sources
+const SOURCES_HASH_FILE: &str = ".gumpi-sources-hash";
+
+/// Computes the hash of the sources tarball and the build settings,
+/// which identifies the build
+fn sources_hash(archive: &SourceArchive, sources: &Sources) -> Fallible<String> {
+    // The build settings affect the build, but the location of the tarball doesn't
+    let settings = Sources {
+        path: PathBuf::new(),
+        ..sources.clone()
     };
 
-    let mut commands = vec![download_cmd];
-    let compile_commands = match mode {
-        BuildType::Make => {
-            let make_cmd = Command::Exec {
-                executable: "make".to_owned(),
-                args: vec!["-C".to_owned(), APP_SOURCES_PATH.to_owned()],
-                working_dir: APP_SOURCES_PATH.to_owned().into(),
+    let mut hasher = Sha256::new();
+    match archive {
+        SourceArchive::File(path) => hasher.update(&transfer::sha256_file(path).context(
+            format!("reading the source archive {}", path.to_string_lossy()),
+        )?),
+        SourceArchive::Packed(data) => hasher.update(data),
+    }
+    hasher.update(serde_json::to_string(&settings)?.as_bytes());
+    Ok(to_hex(&hasher.finish()))
+}
+
+/// Checks if the provider has already built the sources with the given hash
+fn has_build(
+    session: &PeerSession,
+    hash: &str,
+) -> impl Future<Item = bool, Error = failure::Error> {
+    let cmd = Command::Exec {
+        executable: "cat".to_owned(),
+        args: vec![format!("{}/{}", APP_SOURCES_PATH, SOURCES_HASH_FILE)],
+        working_dir: APP_SOURCES_PATH.to_owned().into(),
+    };
+    let hash = hash.to_owned();
+    // A missing hash file means there's no build yet
+    session.update(vec![cmd]).then(move |res| {
+        let built = match res {
+            Ok(outs) => outs.last().map_or(false, |out| out.trim() == hash),
+            Err(_) => false,
+        };
+        Ok::<_, failure::Error>(built)
+    })
+}
+
+/// Uploads the sources and builds them on the targets, recording the hash of the sources
+fn build_sources(
+    hub_session: HubSession,
+    (archive, format): (SourceArchive, ArchiveFormat),
+    sources: Sources,
+    hash: String,
+    targets: Vec<BuildTarget>,
+    digests: Digests,
+) -> impl Future<Item = DeploymentInfo, Error = failure::Error> {
+    let groups: Vec<_> = build_groups(targets, sources.build_on)
+        .into_iter()
+        .map(|group| {
+            // The build is distributed through the hub to the rest of the group
+            let blob = if group.len() > 1 {
+                Some(hub_session.new_blob())
+            } else {
+                None
             };
-            vec![make_cmd]
+            (group, blob)
+        })
+        .collect();
+    let nodes = groups
+        .iter()
+        .flat_map(|(group, _)| group)
+        .map(|target| target.session.node_id().to_string());
+    let progress = Rc::new(progress::Nodes::new("Building".to_owned(), nodes));
+
+    let name = format!("the sources from {}", sources.path.to_string_lossy());
+    let upload = match archive {
+        SourceArchive::File(path) => Either::A(upload_to_hub(&hub_session, &path)),
+        SourceArchive::Packed(data) => {
+            Either::B(upload_data_to_hub(&hub_session, name.clone(), data))
         }
-        BuildType::CMake => {
-            let cmake_cmd = Command::Exec {
-                executable: "cmake".to_owned(),
-                args: vec![
-                    ".".to_owned(),
-                    "-DCMAKE_C_COMPILER=mpicc".to_owned(),
-                    "-DCMAKE_CXX_COMPILER=mpicxx".to_owned(),
-                    "-DCMAKE_BUILD_TYPE=Release".to_owned(),
-                ],
-                working_dir: APP_SOURCES_PATH.to_owned().into(),
+    };
+    upload
+        .context("uploading the source archive")
+        .and_then(move |(blob, digest)| {
+            info!("Building the application on provider nodes");
+            digests
+                .borrow_mut()
+                .insert(format!("{} (upload)", name), digest.clone());
+            let uri = blob.uri();
+            let group_futs = groups
+                .into_iter()
+                .map(move |(mut group, copy_blob)| {
+                    let builder = group.remove(0);
+                    // The number of parallel build jobs depends on the provider
+                    let mut cmds = generate_deployment_cmds(
+                        uri.clone(),
+                        &digest,
+                        format,
+                        &sources,
+                        builder.cores,
+                    );
+                    // The hash is copied along with the build
+                    cmds.push(Command::WriteFile {
+                        content: hash.clone(),
+                        file_path: format!("{}/{}", &APP_SOURCES_PATH[1..], SOURCES_HASH_FILE),
+                    });
+                    let others: Vec<_> = group.into_iter().map(|target| target.session).collect();
+                    let progress = Rc::clone(&progress);
+
+                    build_on_node(builder.session.clone(), cmds, Rc::clone(&progress)).and_then(
+                        move |info| match copy_blob {
+                            Some(blob) => {
+                                Either::A(copy_build(builder.session, others, blob, progress).map(
+                                    move |mut infos| {
+                                        infos.insert(0, info);
+                                        infos
+                                    },
+                                ))
+                            }
+                            None => Either::B(future::ok(vec![info])),
+                        },
+                    )
+                })
+                .collect::<Vec<_>>();
+
+            // All the groups are waited for, to report every failing build at once
+            let group_futs = group_futs
+                .into_iter()
+                .map(|fut| fut.then(Ok::<_, failure::Error>));
+            future::join_all(group_futs).and_then(|results| {
+                let mut logs = vec![];
+                let mut failures = vec![];
+                for res in results {
+                    match res {
+                        Ok(infos) => logs.extend(infos),
+                        Err(e) => match e.downcast::<Error>() {
+                            Ok(Error::CompilationError(fails)) => failures.extend(fails),
+                            Ok(e) => return Err(e.into()),
+                            Err(e) => return Err(e),
+                        },
+                    }
+                }
+                if failures.is_empty() {
+                    Ok(DeploymentInfo { logs })
+                } else {
+                    Err(Error::CompilationError(failures).into())
+                }
+            })
+        })
+}
+
+/// Splits the targets into the groups sharing a single build
+///
+/// With `BuildOn::Platform`, the providers of the same architecture are grouped
+/// together. All the providers run the same Linux image, so the architecture
+/// identifies the platform. The providers with an unknown architecture always
+/// build on their own.
+///
+/// Returns: the groups of the targets, the first one in every group does the build
+fn build_groups(targets: Vec<BuildTarget>, build_on: BuildOn) -> Vec<Vec<BuildTarget>> {
+    let mut groups: Vec<(Option<String>, Vec<_>)> = vec![];
+    for target in targets {
+        let key = match build_on {
+            BuildOn::Node => None,
+            BuildOn::Platform => target.platform.clone(),
+        };
+        let group = match &key {
+            Some(_) => groups.iter_mut().find(|(k, _)| *k == key),
+            None => None,
+        };
+        match group {
+            Some((_, group)) => group.push(target),
+            None => groups.push((key, vec![target])),
+        }
+    }
+    groups.into_iter().map(|(_, group)| group).collect()
+}
+
+/// Runs the build commands on the provider
+///
+/// Every output in the logs is preceded by the description of its command.
+fn build_on_node(
+    session: PeerSession,
+    cmds: Vec<Command>,
+    progress: Rc<progress::Nodes>,
+) -> impl Future<Item = CompilationInfo, Error = failure::Error> {
+    let node = session.node_id();
+    let node_name = node.to_string();
+    debug!(
+        "Executing the following build commands on {}: {:#?}",
+        node.to_string(),
+        cmds
+    );
+    let steps: Vec<_> = cmds.iter().map(describe_cmd).collect();
+    let label = |outs: Vec<String>, steps: &[String]| -> Vec<String> {
+        outs.into_iter()
+            .zip(steps)
+            .map(|(out, step)| format!("$ {}\n{}", step, out))
+            .collect()
+    };
+    let start = Instant::now();
+    progress.set(&node_name, "compiling");
+    session.update(cmds).then(move |res| match res {
+        Ok(outs) => {
+            progress.done(&node_name, start.elapsed());
+            Ok(CompilationInfo {
+                logs: label(outs, &steps),
+                node,
+                duration: start.elapsed(),
+            })
+        }
+        Err(GUError::ProcessingResult(outs)) => {
+            // The outputs end with the one of the failing command
+            let step = steps
+                .get(outs.len().saturating_sub(1))
+                .cloned()
+                .unwrap_or_default();
+            progress.set(&node_name, &format!("failed at `{}`", step));
+            let failure = CompilationFailure {
+                node,
+                step,
+                logs: label(outs, &steps),
             };
-            let make_cmd = Command::Exec {
-                executable: "make".to_owned(),
-                args: vec![],
-                working_dir: APP_SOURCES_PATH.to_owned().into(),
+            Err(Error::CompilationError(vec![failure]).into())
+        }
+        Err(e) => {
+            progress.set(&node_name, "failed");
+            Err(failure::Error::from(e)
+                .context(format!("compiling the app on node {}", node_name))
+                .into())
+        }
+    })
+}
+
+/// Returns: a short, human-readable description of the command
+fn describe_cmd(cmd: &Command) -> String {
+    match cmd {
+        Command::Exec {
+            executable, args, ..
+        } => iter::once(executable)
+            .chain(args)
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+            .join(" "),
+        Command::DownloadFile { file_path, .. } => format!("download to {}", file_path),
+        Command::UploadFile { file_path, .. } => format!("upload from {}", file_path),
+        Command::WriteFile { file_path, .. } => format!("write {}", file_path),
+        cmd => format!("{:?}", cmd),
+    }
+}
+
+/// Copies the built application tree from `builder` to the `others`, through the hub
+fn copy_build<F>(
+    builder: PeerSession,
+    others: Vec<PeerSession>,
+    blob: F,
+    progress: Rc<progress::Nodes>,
+) -> impl Future<Item = Vec<CompilationInfo>, Error = failure::Error>
+where
+    F: Future<Item = Blob, Error = GUError>,
+{
+    let source = builder.node_id();
+    for session in &others {
+        let state = format!("waiting for the build from {}", source.to_string());
+        progress.set(&session.node_id().to_string(), &state);
+    }
+    blob.from_err()
+        .and_then(move |blob| {
+            info!("Uploading the build from {} to the hub", source.to_string());
+            let cmd = Command::UploadFile {
+                file_path: APP_SOURCES_PATH.to_owned(),
+                format: ResourceFormat::Tar,
+                uri: blob.uri(),
+            };
+            builder
+                .update(vec![cmd])
+                .context(format!(
+                    "uploading the build from node {} to the hub",
+                    source.to_string()
+                ))
+                .map(move |_| blob)
+        })
+        .and_then(move |blob| {
+            let download_cmd = Command::DownloadFile {
+                format: ResourceFormat::Tar,
+                uri: blob.uri(),
+                file_path: APP_SOURCES_PATH.to_owned(),
             };
-            vec![cmake_cmd, make_cmd]
+            let futures = others.into_iter().map(move |session| {
+                let node = session.node_id();
+                let node_name = node.to_string();
+                let progress = Rc::clone(&progress);
+                let start = Instant::now();
+                let update = session.update(vec![download_cmd.clone()]);
+                tracked(update, progress, node_name, "copying the build")
+                    .context(format!("copying the build to node {}", node.to_string()))
+                    .map(move |_| CompilationInfo {
+                        logs: vec![format!("Copied the build from {}", source.to_string())],
+                        node,
+                        duration: start.elapsed(),
+                    })
+            });
+            future::join_all(futures)
+        })
+}
+
+/// Shows the `update` of the `node` in the `state`, then as done or failed
+fn tracked<F>(
+    update: F,
+    progress: Rc<progress::Nodes>,
+    node: String,
+    state: &str,
+) -> impl Future<Item = F::Item, Error = F::Error>
+where
+    F: Future,
+{
+    let start = Instant::now();
+    progress.set(&node, state);
+    update.then(move |res| {
+        match &res {
+            Ok(_) => progress.done(&node, start.elapsed()),
+            Err(_) => progress.set(&node, "failed"),
+        }
+        res
+    })
+}
+
+/// Runs the commands on all the sessions, showing the `phase` on every node
+///
+/// Returns: the time it took on every provider
+fn update_all(
+    sessions: Vec<PeerSession>,
+    cmds: Vec<Command>,
+    phase: String,
+) -> impl Future<Item = Vec<(NodeId, Duration)>, Error = failure::Error> {
+    let nodes = sessions.iter().map(|session| session.node_id().to_string());
+    let progress = Rc::new(progress::Nodes::new(phase, nodes));
+    let futures = sessions.into_iter().map(move |session| {
+        let node = session.node_id();
+        let node_name = node.to_string();
+        let start = Instant::now();
+        let update = session.update(cmds.clone());
+        tracked(update, Rc::clone(&progress), node_name.clone(), "running")
+            .map_err(move |e| match e {
+                // The output of the failing command, e.g. a checksum mismatch, explains the error
+                GUError::ProcessingResult(outs) => format_err!(
+                    "node {}: {}",
+                    node_name,
+                    outs.last().map_or("", |out| out.trim())
+                ),
+                x => x.into(),
+            })
+            .map(move |_| (node, start.elapsed()))
+    });
+    future::join_all(futures)
+}
+
+/// Returns: the temporary location of the archive on the provider
+///
+/// The location is unique for every transfer, since several archives may be
+/// transferred to the same directory at once.
+fn provider_archive_path(dir: &str, format: ArchiveFormat) -> String {
+    static TRANSFERS: AtomicUsize = AtomicUsize::new(0);
+    format!(
+        "/tmp/gumpi-{}-{}-{}.{}",
+        process::id(),
+        TRANSFERS.fetch_add(1, Ordering::Relaxed),
+        dir.trim_matches('/').replace('/', "-"),
+        format.extension()
+    )
+}
+
+/// Generates the commands downloading the archive and unpacking it to `destination`
+///
+/// The archive is unpacked only if its SHA-256 digest matches `digest`.
+fn download_cmds(
+    uri: String,
+    format: ArchiveFormat,
+    destination: &str,
+    digest: &str,
+) -> Vec<Command> {
+    let archive = provider_archive_path(destination, format);
+    let script = format!(
+        "{verify} && {check} && mkdir -p {dest} && {extract} && rm -f {archive}",
+        verify = transfer::verify_cmd(&shell_quote(&archive), digest),
+        check = format.extract_check_cmd(),
+        dest = shell_quote(destination),
+        extract = format.extract_cmd(&shell_quote(&archive), &shell_quote(destination)),
+        archive = shell_quote(&archive),
+    );
+    vec![
+        Command::DownloadFile {
+            format: ResourceFormat::Raw,
+            uri,
+            file_path: archive,
+        },
+        Command::Exec {
+            executable: "sh".to_owned(),
+            args: vec!["-c".to_owned(), script],
+            working_dir: "/".to_owned().into(),
+        },
+    ]
+}
+
+/// The settings of the archive retrieved from a provider
+struct ArchiveTransfer {
+    format: ArchiveFormat,
+    /// the download fails if the archive is larger, in bytes
+    max_size: Option<u64>,
+    filter: FileFilter,
+}
+
+/// Generates the commands packing the `source` directory and uploading it to `uri`
+///
+/// Only the files selected by the `filter` are packed. The output of the second
+/// command is the SHA-256 digest of the archive.
+fn upload_cmds(
+    uri: String,
+    format: ArchiveFormat,
+    source: &str,
+    filter: &FileFilter,
+) -> Vec<Command> {
+    let archive = provider_archive_path(source, format);
+    let exec = |script: String| Command::Exec {
+        executable: "sh".to_owned(),
+        args: vec!["-c".to_owned(), script],
+        working_dir: "/".to_owned().into(),
+    };
+    let compress = if filter.is_empty() {
+        format.compress_cmd(&shell_quote(source), &shell_quote(&archive))
+    } else {
+        // Without any file, zip fails with an obscure status, and an empty tarball is no use
+        let list = shell_quote(&format!("{}.list", archive));
+        format!(
+            "(cd {source} && {find}) > {list} && \
+             if [ ! -s {list} ]; then echo {error} >&2; rm -f {list}; exit 1; fi && \
+             {compress} && rm -f {list}",
+            source = shell_quote(source),
+            find = find_cmd(filter),
+            list = list,
+            error = shell_quote(NO_MATCHING_FILES),
+            compress =
+                format.compress_list_cmd(&shell_quote(source), &list, &shell_quote(&archive)),
+        )
+    };
+    vec![
+        exec(format!(
+            "{check} && rm -f {archive} && {compress}",
+            check = format.compress_check_cmd(),
+            archive = shell_quote(&archive),
+            compress = compress,
+        )),
+        Command::Exec {
+            executable: "sha256sum".to_owned(),
+            args: vec![archive.clone()],
+            working_dir: "/".to_owned().into(),
+        },
+        Command::UploadFile {
+            file_path: archive.clone(),
+            format: ResourceFormat::Raw,
+            uri,
+        },
+        exec(format!("rm -f {}", shell_quote(&archive))),
+    ]
+}
+
+const NO_MATCHING_FILES: &str = "no output files match the include/exclude patterns";
+
+/// Returns: the `find` command listing the files selected by the filter,
+/// relative to the current directory
+///
+/// A pattern matching a directory matches all the files in it, too.
+fn find_cmd(filter: &FileFilter) -> String {
+    let any_of = |patterns: &[String]| {
+        let tests: Vec<_> = patterns
+            .iter()
+            .map(|pattern| {
+                format!(
+                    "-path {} -o -path {}",
+                    shell_quote(&format!("./{}", pattern)),
+                    shell_quote(&format!("./{}/*", pattern))
+                )
+            })
+            .collect();
+        format!("\\( {} \\)", tests.join(" -o "))
+    };
+
+    let mut cmd = "find . \\( -type f -o -type l \\)".to_owned();
+    if !filter.include.is_empty() {
+        cmd += &format!(" {}", any_of(&filter.include));
+    }
+    if !filter.exclude.is_empty() {
+        cmd += &format!(" ! {}", any_of(&filter.exclude));
+    }
+    cmd + " -print"
+}
+
+/// Returns: the directory the build puts the binary in
+pub fn binary_dir(sources: &Sources) -> String {
+    match sources.mode {
+        BuildType::Make | BuildType::Script => APP_SOURCES_PATH.to_owned(),
+        BuildType::CMake | BuildType::Autotools | BuildType::Meson => build_dir(sources),
+        BuildType::Cargo => format!("{}/release", build_dir(sources)),
+    }
+}
+
+/// Returns: the build directory, relative paths are resolved against the sources
+fn build_dir(sources: &Sources) -> String {
+    let dir = match (&sources.build_dir, &sources.mode) {
+        (Some(dir), _) => dir.as_path(),
+        (None, BuildType::Meson) => Path::new("build"),
+        (None, BuildType::Cargo) => Path::new("target"),
+        (None, _) => return APP_SOURCES_PATH.to_owned(),
+    };
+    Path::new(APP_SOURCES_PATH)
+        .join(dir)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Prepares a build step, setting the build environment variables
+///
+/// The variables in `sources.env` take precedence over the defaults in `env`.
+fn build_step(
+    sources: &Sources,
+    env: &[(&str, &str)],
+    working_dir: &str,
+    executable: &str,
+    args: Vec<String>,
+) -> Command {
+    let env: Vec<_> = env
+        .iter()
+        .map(|(var, value)| format!("{}={}", var, value))
+        .chain(
+            sources
+                .env
+                .iter()
+                .map(|(var, value)| format!("{}={}", var, value)),
+        )
+        .collect();
+    let (executable, args) = if env.is_empty() {
+        (executable.to_owned(), args)
+    } else {
+        let mut env_args = env;
+        env_args.push(executable.to_owned());
+        env_args.extend(args);
+        ("env".to_owned(), env_args)
+    };
+    Command::Exec {
+        executable,
+        args,
+        working_dir: working_dir.to_owned().into(),
+    }
+}
+
+/// Generates the commands building the sources on a provider with `cores` CPU cores
+fn generate_deployment_cmds(
+    uri: String,
+    digest: &str,
+    format: ArchiveFormat,
+    sources: &Sources,
+    cores: usize,
+) -> Vec<Command> {
+    const MPI_COMPILERS: &[(&str, &str)] = &[("CC", "mpicc"), ("CXX", "mpicxx")];
+    // Ninja and Cargo build in parallel anyway
+    let jobs = sources.jobs.unwrap_or(cores).to_string();
+    let build_dir = build_dir(sources);
+    let out_of_tree = build_dir != APP_SOURCES_PATH;
+    let step = |working_dir: &str, executable: &str, args: Vec<String>| {
+        build_step(sources, &[], working_dir, executable, args)
+    };
+    let mkdir = || Command::Exec {
+        executable: "mkdir".to_owned(),
+        args: vec!["-p".to_owned(), build_dir.clone()],
+        working_dir: APP_SOURCES_PATH.to_owned().into(),
+    };
+    // Not every Makefile is safe for parallel builds, so make runs
+    // sequentially unless the number of jobs is given
+    let make = |working_dir: &str, mut args: Vec<String>| {
+        if let Some(jobs) = sources.jobs {
+            args.extend(vec!["-j".to_owned(), jobs.to_string()]);
         }
+        args.extend(sources.targets.iter().cloned());
+        step(working_dir, "make", args)
     };
-    commands.extend(compile_commands);
+
+    let mut commands = download_cmds(uri, format, APP_SOURCES_PATH, digest);
+    match sources.mode {
+        BuildType::Make => {
+            commands.push(make(
+                APP_SOURCES_PATH,
+                vec!["-C".to_owned(), APP_SOURCES_PATH.to_owned()],
+            ));
+        }
+        BuildType::CMake => {
+            if out_of_tree {
+                commands.push(mkdir());
+            }
+            let build_type = sources
+                .build_type
+                .as_ref()
+                .map_or("Release", String::as_str);
+            let mut args = vec![
+                APP_SOURCES_PATH.to_owned(),
+                "-DCMAKE_C_COMPILER=mpicc".to_owned(),
+                "-DCMAKE_CXX_COMPILER=mpicxx".to_owned(),
+                format!("-DCMAKE_BUILD_TYPE={}", build_type),
+            ];
+            args.extend(
+                sources
+                    .defines
+                    .iter()
+                    .map(|(var, value)| format!("-D{}={}", var, value)),
+            );
+            commands.push(step(build_dir.as_str(), "cmake", args));
+            commands.push(make(build_dir.as_str(), vec![]));
+        }
+        BuildType::Autotools => {
+            // The tarballs created from a repository usually lack the configure script
+            commands.push(step(
+                APP_SOURCES_PATH,
+                "sh",
+                vec![
+                    "-c".to_owned(),
+                    "test -x ./configure || autoreconf --install".to_owned(),
+                ],
+            ));
+            if out_of_tree {
+                commands.push(mkdir());
+            }
+            let configure = format!("{}/configure", APP_SOURCES_PATH);
+            let args = MPI_COMPILERS
+                .iter()
+                .map(|(var, value)| format!("{}={}", var, value))
+                .collect();
+            commands.push(step(build_dir.as_str(), &configure, args));
+            commands.push(make(build_dir.as_str(), vec![]));
+        }
+        BuildType::Meson => {
+            let build_type = sources
+                .build_type
+                .as_ref()
+                .map_or("release", String::as_str);
+            let mut args = vec![build_dir.clone(), format!("--buildtype={}", build_type)];
+            args.extend(
+                sources
+                    .defines
+                    .iter()
+                    .map(|(var, value)| format!("-D{}={}", var, value)),
+            );
+            commands.push(build_step(
+                sources,
+                MPI_COMPILERS,
+                APP_SOURCES_PATH,
+                "meson",
+                args,
+            ));
+
+            let mut args = vec![
+                "-C".to_owned(),
+                build_dir.clone(),
+                "-j".to_owned(),
+                jobs.clone(),
+            ];
+            args.extend(sources.targets.iter().cloned());
+            commands.push(step(APP_SOURCES_PATH, "ninja", args));
+        }
+        // rsmpi finds the MPI installation using mpicc
+        BuildType::Cargo => {
+            let mut args = vec![
+                "build".to_owned(),
+                "--release".to_owned(),
+                "-j".to_owned(),
+                jobs.clone(),
+                "--target-dir".to_owned(),
+                build_dir.clone(),
+            ];
+            for target in &sources.targets {
+                args.extend(vec!["--bin".to_owned(), target.clone()]);
+            }
+            commands.push(step(APP_SOURCES_PATH, "cargo", args));
+        }
+        BuildType::Script => {
+            // The presence of the script is checked when reading the job config
+            let script = sources
+                .script
+                .as_ref()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            commands.push(step(APP_SOURCES_PATH, "sh", vec![script]));
+        }
+    }
     commands
 }
 
+/// Prepares the arguments of runuser, running the program using mpirun
+///
+/// With `libdir`, the directory is prepended to the `LD_LIBRARY_PATH` of the image
+/// and the result is exported to all the ranks.
+fn mpirun_cmdline(
+    nproc: usize,
+    progname: String,
+    args: Vec<String>,
+    mpiargs: Vec<String>,
+    bindir: Option<String>,
+    libdir: Option<String>,
+) -> Vec<String> {
+    // We execute the program on the root provider in the following manner:
+    //      runuser -u mpirun -- mpirun /path/to/executable arg1 arg2
+
+    let mut cmdline = vec![];
+
+    // We use runuser to make sure we're not running as root
+    let runuser_args = vec!["-u", GUMPI_DOCKER_USER, "--"]
+        .into_iter()
+        .map(ToOwned::to_owned);
+    cmdline.extend(runuser_args);
+
+    // The variable has to be extended by a shell, since runuser doesn't expand it
+    if let Some(dir) = &libdir {
+        let script = format!(
+            "LD_LIBRARY_PATH={}${{LD_LIBRARY_PATH:+:$LD_LIBRARY_PATH}}; \
+             export LD_LIBRARY_PATH; exec \"$@\"",
+            shell_quote(dir)
+        );
+        cmdline.extend(vec![
+            "sh".to_owned(),
+            "-c".to_owned(),
+            script,
+            "sh".to_owned(),
+        ]);
+    }
+
+    // ... to call mpirun, first adding gumpi-logic arguments, later the
+    // custom user defined arguments
+    cmdline.push("mpirun".to_owned());
+    cmdline.extend(vec![
+        "-n".to_owned(),
+        nproc.to_string(),
+        "--hostfile".to_owned(),
+        "/hostfile".to_owned(),
+    ]);
+    if libdir.is_some() {
+        cmdline.extend(vec!["-x".to_owned(), "LD_LIBRARY_PATH".to_owned()]);
+    }
+    cmdline.extend(mpiargs);
+
+    // ... then the program name ...
+    //
+    // If we've built the sources, we need to give the exact path to the binary
+    // Otherwise it's somewhere on the system, so let the user decide
+    let progname = match bindir {
+        Some(dir) => format!("{}/{}", dir, progname),
+        None => progname,
+    };
+    cmdline.push(progname);
+
+    // Finally the user-defined applicadtion arguments
+    cmdline.extend(args);
+    cmdline
+}
+
+/// Checks if the IP address occurs in the text as a whole,
+/// e.g. 10.0.0.1 is not mentioned in "10.0.0.12"
+fn mentions_ip(text: &str, ip: &str) -> bool {
+    text.match_indices(ip).any(|(idx, _)| {
+        let before = text[..idx].chars().next_back();
+        let mut after = text[idx + ip.len()..].chars();
+        let continued_before = before.map_or(false, |c| c.is_alphanumeric() || c == '.');
+        let continued_after = match (after.next(), after.next()) {
+            (Some('.'), Some(c)) => c.is_alphanumeric(),
+            (Some(c), _) => c.is_alphanumeric(),
+            (None, _) => false,
+        };
+        !continued_before && !continued_after
+    })
+}
+
+/// Quotes a string, so that it's passed as a single argument by the shell
+/// Parses the bytes dumped by `od -An -v -tx1`
+fn parse_hex_dump(dump: &str) -> Fallible<Vec<u8>> {
+    dump.split_whitespace()
+        .map(|byte| {
+            u8::from_str_radix(byte, 16)
+                .map_err(|_| format_err!("invalid byte in the job output dump: {}", byte))
+        })
+        .collect()
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
 fn generate_keypair() -> Fallible<(String, String)> {
     use openssh_keys::PublicKey;
     use openssl::rsa::Rsa;
@@ -555,3 +1998,189 @@ fn generate_keypair() -> Fallible<(String, String)> {
 
     Ok((privkey, pubkey))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mentions_ip() {
+        assert!(mentions_ip("Remote host: 10.0.0.1", "10.0.0.1"));
+        assert!(mentions_ip("connect to 10.0.0.1:4222 failed", "10.0.0.1"));
+        assert!(mentions_ip("on node 10.0.0.1.", "10.0.0.1"));
+        assert!(!mentions_ip("Remote host: 10.0.0.12", "10.0.0.1"));
+        assert!(!mentions_ip("Remote host: 110.0.0.1", "10.0.0.1"));
+        assert!(!mentions_ip("Remote host: 10.0.0.1.5", "10.0.0.1"));
+    }
+
+    #[test]
+    fn test_parse_hex_dump() {
+        // "é" split across two lines
+        let dump = " 61 0a c3\n a9 62\n";
+        assert_eq!(parse_hex_dump(dump).unwrap(), "a\n\u{e9}b".as_bytes());
+        assert_eq!(parse_hex_dump("").unwrap(), b"");
+        assert!(parse_hex_dump(" 6g").is_err());
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("abc"), "'abc'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+
+    #[test]
+    fn test_deployment_cmds() {
+        let mut sources = Sources {
+            path: "app.tar".into(),
+            revision: None,
+            mode: BuildType::CMake,
+            script: None,
+            defines: vec![("USE_OPENMP".to_owned(), "OFF".to_owned())]
+                .into_iter()
+                .collect(),
+            build_type: None,
+            targets: vec!["app".to_owned()],
+            jobs: None,
+            env: vec![("CFLAGS".to_owned(), "-O3".to_owned())]
+                .into_iter()
+                .collect(),
+            build_dir: Some("build".into()),
+            build_on: BuildOn::Node,
+        };
+        assert_eq!(binary_dir(&sources), "/app/build");
+
+        let cmds =
+            generate_deployment_cmds("uri".to_owned(), "0123", ArchiveFormat::Tar, &sources, 8);
+        // The sources are verified before being unpacked
+        match &cmds[1] {
+            Command::Exec { args, .. } => assert!(args[1].contains("!= 0123")),
+            cmd => panic!("unexpected command: {:?}", cmd),
+        }
+        // The download commands are followed by the build steps
+        let execs: Vec<_> = cmds
+            .iter()
+            .skip(2)
+            .filter_map(|cmd| match cmd {
+                Command::Exec {
+                    executable, args, ..
+                } => Some((executable.as_str(), args.join(" "))),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            execs,
+            vec![
+                ("mkdir", "-p /app/build".to_owned()),
+                (
+                    "env",
+                    "CFLAGS=-O3 cmake /app -DCMAKE_C_COMPILER=mpicc -DCMAKE_CXX_COMPILER=mpicxx \
+                     -DCMAKE_BUILD_TYPE=Release -DUSE_OPENMP=OFF"
+                        .to_owned()
+                ),
+                ("env", "CFLAGS=-O3 make app".to_owned()),
+            ]
+        );
+
+        sources.jobs = Some(4);
+        let cmds =
+            generate_deployment_cmds("uri".to_owned(), "0123", ArchiveFormat::Tar, &sources, 8);
+        match cmds.last() {
+            Some(Command::Exec { args, .. }) => {
+                assert_eq!(args.join(" "), "CFLAGS=-O3 make -j 4 app")
+            }
+            cmd => panic!("unexpected command: {:?}", cmd),
+        }
+
+        sources.mode = BuildType::Cargo;
+        sources.build_dir = None;
+        assert_eq!(binary_dir(&sources), "/app/target/release");
+    }
+
+    #[test]
+    fn test_mpirun_cmdline() {
+        let cmdline = mpirun_cmdline(
+            4,
+            "app".to_owned(),
+            vec!["input.txt".to_owned()],
+            vec![],
+            Some("/app".to_owned()),
+            Some("/app/lib".to_owned()),
+        );
+        assert_eq!(&cmdline[3..5], ["sh", "-c"]);
+        assert_eq!(
+            cmdline[5],
+            "LD_LIBRARY_PATH='/app/lib'${LD_LIBRARY_PATH:+:$LD_LIBRARY_PATH}; \
+             export LD_LIBRARY_PATH; exec \"$@\""
+        );
+        assert_eq!(
+            &cmdline[6..],
+            [
+                "sh",
+                "mpirun",
+                "-n",
+                "4",
+                "--hostfile",
+                "/hostfile",
+                "-x",
+                "LD_LIBRARY_PATH",
+                "/app/app",
+                "input.txt"
+            ]
+        );
+
+        let cmdline = mpirun_cmdline(4, "app".to_owned(), vec![], vec![], None, None);
+        assert_eq!(cmdline[3], "mpirun");
+    }
+
+    #[test]
+    fn test_provider_archive_path() {
+        let first = provider_archive_path("/input", ArchiveFormat::TarGz);
+        let second = provider_archive_path("/input", ArchiveFormat::TarGz);
+        assert!(first.starts_with("/tmp/gumpi-"));
+        assert!(first.ends_with("-input.tar.gz"));
+        assert_ne!(first, second);
+    }
+
+    #[test]
+    fn test_find_cmd() {
+        let filter = FileFilter {
+            include: vec!["*.csv".to_owned(), "summary.txt".to_owned()],
+            exclude: vec!["scratch".to_owned()],
+        };
+        assert_eq!(
+            find_cmd(&filter),
+            concat!(
+                r"find . \( -type f -o -type l \)",
+                r" \( -path './*.csv' -o -path './*.csv/*'",
+                r" -o -path './summary.txt' -o -path './summary.txt/*' \)",
+                r" ! \( -path './scratch' -o -path './scratch/*' \) -print"
+            )
+        );
+        assert_eq!(
+            find_cmd(&FileFilter::default()),
+            r"find . \( -type f -o -type l \) -print"
+        );
+
+        let cmds = upload_cmds("uri".to_owned(), ArchiveFormat::Zip, "/output", &filter);
+        assert_eq!(cmds.len(), 4);
+        match &cmds[0] {
+            Command::Exec { args, .. } => assert!(args[1].contains(NO_MATCHING_FILES)),
+            cmd => panic!("unexpected command: {:?}", cmd),
+        }
+    }
+
+    #[test]
+    fn test_describe_cmd() {
+        let exec = Command::Exec {
+            executable: "make".to_owned(),
+            args: vec!["-j".to_owned(), "4".to_owned()],
+            working_dir: "/app".to_owned().into(),
+        };
+        assert_eq!(describe_cmd(&exec), "make -j 4");
+        let write = Command::WriteFile {
+            content: "hash".to_owned(),
+            file_path: "app/.gumpi-sources-hash".to_owned(),
+        };
+        assert_eq!(describe_cmd(&write), "write app/.gumpi-sources-hash");
+    }
+}
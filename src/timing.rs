@@ -0,0 +1,219 @@
+use futures::{future, prelude::*};
+use serde_derive::Serialize;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// Converts a `Duration` to fractional seconds
+pub fn as_secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9
+}
+
+/// Wall time statistics of repeated runs of a job
+///
+/// All the times are expressed in seconds. The warm-up runs are listed
+/// in `warmup_times`, but are excluded from the statistics.
+#[derive(Debug, Serialize)]
+pub struct TimingSummary {
+    pub runs: usize,
+    pub warmup: usize,
+    pub warmup_times: Vec<f64>,
+    pub times: Vec<f64>,
+    pub min: f64,
+    pub median: f64,
+    pub mean: f64,
+    pub stddev: f64,
+}
+
+impl TimingSummary {
+    /// Returns `None` if there are no runs left after discarding the warm-up runs
+    pub fn new(durations: &[Duration], warmup: usize) -> Option<Self> {
+        if durations.len() <= warmup {
+            return None;
+        }
+        let (warmup_times, times) = durations.split_at(warmup);
+        let warmup_times: Vec<_> = warmup_times.iter().cloned().map(as_secs).collect();
+        let times: Vec<_> = times.iter().cloned().map(as_secs).collect();
+
+        let mut sorted = times.clone();
+        sorted.sort_by(|a, b| a.partial_cmp(b).expect("NaN in the run times"));
+
+        let n = sorted.len();
+        let median = if n % 2 == 0 {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+        } else {
+            sorted[n / 2]
+        };
+        let mean = sorted.iter().sum::<f64>() / n as f64;
+        // Sample standard deviation, a single run has no spread at all
+        let stddev = if n > 1 {
+            let sq_sum: f64 = sorted.iter().map(|t| (t - mean) * (t - mean)).sum();
+            (sq_sum / (n - 1) as f64).sqrt()
+        } else {
+            0.0
+        };
+
+        Some(Self {
+            runs: durations.len(),
+            warmup,
+            warmup_times,
+            times,
+            min: sorted[0],
+            median,
+            mean,
+            stddev,
+        })
+    }
+}
+
+impl fmt::Display for TimingSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        writeln!(
+            f,
+            "{} runs ({} warm-up runs discarded)",
+            self.runs, self.warmup
+        )?;
+        writeln!(f, "min:    {:.3} s", self.min)?;
+        writeln!(f, "median: {:.3} s", self.median)?;
+        writeln!(f, "mean:   {:.3} s", self.mean)?;
+        write!(f, "stddev: {:.3} s", self.stddev)
+    }
+}
+
+/// The wall time of a single phase of the job, e.g. the compilation
+///
+/// `providers` holds the times measured separately on every provider,
+/// where that applies.
+#[derive(Debug, Serialize)]
+pub struct PhaseTime {
+    pub phase: String,
+    pub seconds: f64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub providers: BTreeMap<String, f64>,
+}
+
+/// The breakdown of the wall time of the job into its phases
+#[derive(Debug, Default, Serialize)]
+pub struct PhaseReport {
+    pub phases: Vec<PhaseTime>,
+    /// The SHA-256 digests of the transferred archives
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checksums: BTreeMap<String, String>,
+}
+
+/// The phase report shared between the futures of a single job
+pub type SharedPhaseReport = Rc<RefCell<PhaseReport>>;
+
+impl PhaseReport {
+    /// The phases are listed in the order they were first recorded
+    fn phase_mut(&mut self, phase: &str) -> &mut PhaseTime {
+        let pos = match self.phases.iter().position(|p| p.phase == phase) {
+            Some(pos) => pos,
+            None => {
+                self.phases.push(PhaseTime {
+                    phase: phase.to_owned(),
+                    seconds: 0.0,
+                    providers: BTreeMap::new(),
+                });
+                self.phases.len() - 1
+            }
+        };
+        &mut self.phases[pos]
+    }
+
+    pub fn record(&mut self, phase: &str, duration: Duration) {
+        self.phase_mut(phase).seconds = as_secs(duration);
+    }
+
+    pub fn record_provider(&mut self, phase: &str, provider: String, duration: Duration) {
+        self.phase_mut(phase)
+            .providers
+            .insert(provider, as_secs(duration));
+    }
+}
+
+impl fmt::Display for PhaseReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        for (i, phase) in self.phases.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{:<24} {:>10.3} s", phase.phase, phase.seconds)?;
+            for (provider, seconds) in &phase.providers {
+                write!(f, "\n  {:<22} {:>10.3} s", provider, seconds)?;
+            }
+        }
+        if !self.checksums.is_empty() {
+            write!(f, "\nChecksums (SHA-256):")?;
+            for (name, digest) in &self.checksums {
+                write!(f, "\n  {} {}", digest, name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Records the time from the first poll of `fut` until it resolves,
+/// whether successfully or not
+pub fn timed<F: Future>(
+    report: &SharedPhaseReport,
+    phase: String,
+    fut: F,
+) -> impl Future<Item = F::Item, Error = F::Error> {
+    let report = Rc::clone(report);
+    future::lazy(move || {
+        let start = Instant::now();
+        fut.then(move |res| {
+            report.borrow_mut().record(&phase, start.elapsed());
+            res
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timing_summary() {
+        let durations: Vec<_> = [10, 2, 4, 4, 4, 5, 5, 7, 9]
+            .iter()
+            .map(|&s| Duration::from_secs(s))
+            .collect();
+        let summary = TimingSummary::new(&durations, 1).unwrap();
+
+        assert_eq!(summary.runs, 9);
+        assert_eq!(summary.warmup_times, vec![10.0]);
+        assert_eq!(summary.times.len(), 8);
+        assert_eq!(summary.min, 2.0);
+        assert_eq!(summary.median, 4.5);
+        assert_eq!(summary.mean, 5.0);
+        assert!((summary.stddev - (32.0f64 / 7.0).sqrt()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_phase_report() {
+        let mut report = PhaseReport::default();
+        report.record_provider("deploy", "0x01".to_owned(), Duration::from_secs(3));
+        report.record_provider("deploy", "0x02".to_owned(), Duration::from_secs(4));
+        report.record("deploy", Duration::from_secs(5));
+        report.record("exec", Duration::from_millis(1500));
+
+        let phases: Vec<_> = report.phases.iter().map(|p| p.phase.as_str()).collect();
+        assert_eq!(phases, vec!["deploy", "exec"]);
+        assert_eq!(report.phases[0].seconds, 5.0);
+        assert_eq!(report.phases[0].providers["0x02"], 4.0);
+        assert_eq!(report.phases[1].seconds, 1.5);
+        assert!(report.phases[1].providers.is_empty());
+    }
+
+    #[test]
+    fn test_timing_summary_only_warmup() {
+        let durations = vec![Duration::from_secs(1)];
+        assert!(TimingSummary::new(&durations, 1).is_none());
+    }
+}
@@ -0,0 +1,143 @@
+use crate::{format_error_chain, jobconfig::ToolchainCheck, session::mpi::SessionMPI};
+use failure::format_err;
+use futures::{
+    future::{self, Either},
+    prelude::*,
+};
+use log::{info, warn};
+use std::collections::BTreeMap;
+
+/// Prints the first line of `mpirun --version` and `mpicc --version`,
+/// followed by the output of `uname -m`, one per line
+///
+/// Every line is printed even if the tool is missing.
+pub const PROBE_SCRIPT: &str = r#"echo "$(mpirun --version 2>&1 | head -n 1)"
+echo "$(mpicc --version 2>&1 | head -n 1)"
+echo "$(uname -m 2>&1)""#;
+
+/// The tools found on a provider
+#[derive(Clone, Debug, PartialEq)]
+pub struct Toolchain {
+    pub node: String,
+    pub mpirun: String,
+    pub mpicc: String,
+    pub arch: String,
+}
+
+impl Toolchain {
+    /// Parses the output of `PROBE_SCRIPT`
+    pub fn parse(node: String, output: &str) -> Self {
+        let mut lines = output.lines().map(|line| line.trim().to_owned());
+        let mut next = || lines.next().unwrap_or_default();
+        Toolchain {
+            node,
+            mpirun: next(),
+            mpicc: next(),
+            arch: next(),
+        }
+    }
+}
+
+/// Returns: the descriptions of the differences between the providers
+pub fn mismatches(toolchains: &[Toolchain]) -> Vec<String> {
+    let tools: [(&str, fn(&Toolchain) -> &str); 3] = [
+        ("MPI versions (mpirun --version)", |t| t.mpirun.as_str()),
+        ("compiler versions (mpicc --version)", |t| t.mpicc.as_str()),
+        ("architectures (uname -m)", |t| t.arch.as_str()),
+    ];
+
+    let mut ret = vec![];
+    for (name, version) in tools.iter() {
+        let mut nodes: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for toolchain in toolchains {
+            nodes
+                .entry(version(toolchain))
+                .or_insert_with(Vec::new)
+                .push(&toolchain.node);
+        }
+        if nodes.len() > 1 {
+            let versions: Vec<_> = nodes
+                .iter()
+                .map(|(version, nodes)| format!("  {}: {}", version, nodes.join(", ")))
+                .collect();
+            ret.push(format!("different {}:\n{}", name, versions.join("\n")));
+        }
+    }
+    ret
+}
+
+/// Checks that all the providers have the same toolchain
+///
+/// Depending on `policy`, the differences are only reported or fail the job.
+pub fn check(
+    session: &SessionMPI,
+    policy: ToolchainCheck,
+) -> impl Future<Item = (), Error = failure::Error> {
+    if policy == ToolchainCheck::Off {
+        return Either::A(future::ok(()));
+    }
+
+    let check = session.probe_toolchains().and_then(move |probes| {
+        let mut toolchains = vec![];
+        for probe in probes {
+            match probe {
+                Ok(toolchain) => toolchains.push(toolchain),
+                Err(e) if policy == ToolchainCheck::Refuse => return Err(e),
+                Err(e) => warn!(
+                    "Skipping the toolchain check of a provider: {}",
+                    format_error_chain(&e)
+                ),
+            }
+        }
+        for toolchain in &toolchains {
+            info!(
+                "Provider {}: {}, {}, {}",
+                toolchain.node, toolchain.mpirun, toolchain.mpicc, toolchain.arch
+            );
+        }
+        let mismatches = mismatches(&toolchains);
+        if mismatches.is_empty() {
+            return Ok(());
+        }
+        let report = mismatches.join("\n");
+        match policy {
+            ToolchainCheck::Refuse => {
+                Err(format_err!("the providers' toolchains differ:\n{}", report))
+            }
+            _ => {
+                warn!("The providers' toolchains differ:\n{}", report);
+                Ok(())
+            }
+        }
+    });
+    Either::B(check)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mismatches() {
+        let probe = |node: &str, mpirun: &str, arch: &str| {
+            let output = format!("{}\ngcc (Debian 8.3.0-6) 8.3.0\n{}\n", mpirun, arch);
+            Toolchain::parse(node.to_owned(), &output)
+        };
+        let a = probe("a", "mpirun (Open MPI) 3.1.3", "x86_64");
+        assert_eq!(a.mpicc, "gcc (Debian 8.3.0-6) 8.3.0");
+        let b = probe("b", "mpirun (Open MPI) 3.1.3", "x86_64");
+        assert!(mismatches(&[a.clone(), b.clone()]).is_empty());
+
+        let c = probe("c", "mpirun (Open MPI) 4.0.2", "aarch64");
+        let found = mismatches(&[a, c]);
+        assert_eq!(found.len(), 2);
+        assert!(found[0].starts_with("different MPI versions"));
+        assert!(found[0].contains("4.0.2: c"));
+        assert!(found[1].starts_with("different architectures"));
+
+        // A missing line is compared as an empty version
+        let d = Toolchain::parse("d".to_owned(), "mpirun (Open MPI) 3.1.3\n");
+        assert_eq!(d.arch, "");
+        assert_eq!(mismatches(&[d, b]).len(), 2);
+    }
+}
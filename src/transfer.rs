@@ -0,0 +1,376 @@
+use crate::progress;
+use actix_web::{
+    client,
+    error::PayloadError,
+    http::{header, StatusCode},
+    HttpMessage,
+};
+use bytes::Bytes;
+use failure::{format_err, Fail, ResultExt};
+use failure_ext::FutureExt;
+use futures::{
+    future::{self, Either, Loop},
+    prelude::*,
+    stream,
+};
+use log::warn;
+use openssl::sha::Sha256;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+/// The size of the chunks the files are uploaded in, which bounds
+/// the memory used by an upload
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+/// Formats the size in bytes using binary prefixes, e.g. `1.5 GiB`
+pub fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Streams the file from the disk, one chunk at a time
+pub struct FileChunks {
+    file: File,
+    progress: progress::Transfer,
+}
+
+impl FileChunks {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let total = file.metadata()?.len();
+        let name = format!("Uploading {}", path.to_string_lossy());
+        Ok(FileChunks {
+            file,
+            progress: progress::Transfer::new(name, total),
+        })
+    }
+}
+
+impl Stream for FileChunks {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Option<Bytes>, io::Error> {
+        let mut buf = vec![0; CHUNK_SIZE];
+        let len = self.file.read(&mut buf)?;
+        if len == 0 {
+            return Ok(Async::Ready(None));
+        }
+        buf.truncate(len);
+        self.progress.advance(len as u64);
+        Ok(Async::Ready(Some(buf.into())))
+    }
+}
+
+/// Splits the data held in memory into chunks, reporting the progress of the upload
+pub fn data_chunks(data: Vec<u8>, name: &str) -> impl Stream<Item = Bytes, Error = io::Error> {
+    let mut data = Bytes::from(data);
+    let mut progress = progress::Transfer::new(format!("Uploading {}", name), data.len() as u64);
+    let mut chunks = vec![];
+    while !data.is_empty() {
+        let len = data.len().min(CHUNK_SIZE);
+        chunks.push(data.split_to(len));
+    }
+    stream::iter_ok(chunks).inspect(move |chunk| progress.advance(chunk.len() as u64))
+}
+
+/// The SHA-256 digests of the transferred files, in hex, by their descriptions
+pub type Digests = Rc<RefCell<BTreeMap<String, String>>>;
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Passes the chunks through, computing their SHA-256 digest
+///
+/// Returns: the stream and the hasher, to be finished once the stream is consumed
+pub fn hashed<S>(
+    chunks: S,
+) -> (
+    impl Stream<Item = Bytes, Error = io::Error>,
+    Rc<RefCell<Sha256>>,
+)
+where
+    S: Stream<Item = Bytes, Error = io::Error>,
+{
+    let hasher = Rc::new(RefCell::new(Sha256::new()));
+    let stream_hasher = Rc::clone(&hasher);
+    let stream = chunks.inspect(move |chunk| stream_hasher.borrow_mut().update(chunk));
+    (stream, hasher)
+}
+
+/// Returns: the shell command failing with a message if the SHA-256 digest of `file`
+/// isn't `expected`
+///
+/// `file` has to be quoted already.
+pub fn verify_cmd(file: &str, expected: &str) -> String {
+    format!(
+        "actual=$(sha256sum {file} | cut -d ' ' -f 1) && \
+         if [ \"$actual\" != {expected} ]; then \
+         echo \"checksum mismatch of {file}: expected {expected}, got $actual\"; exit 1; fi",
+        file = file,
+        expected = expected
+    )
+}
+
+/// The error reported when the digest of the transferred file doesn't match
+#[derive(Debug, Fail)]
+#[fail(
+    display = "checksum mismatch of {}: expected {}, got {}",
+    file, expected, actual
+)]
+pub struct ChecksumMismatch {
+    pub file: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// Computes the SHA-256 digest of the file, reading it in chunks
+pub fn sha256_file(path: &Path) -> io::Result<[u8; 32]> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            return Ok(hasher.finish());
+        }
+        hasher.update(&buf[..len]);
+    }
+}
+
+/// The number of times an interrupted download is resumed
+const MAX_RESUMES: usize = 3;
+
+#[derive(Debug, Fail)]
+#[fail(display = "the download exceeds the size limit of {}", _0)]
+struct SizeLimitExceeded(String);
+
+/// Returns: the temporary file the download to `target` is written to
+fn part_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_owned();
+    name.push(".part");
+    target.with_file_name(name)
+}
+
+/// Streams the resource to `target`, without holding it in memory
+///
+/// The data is written to a temporary file next to `target`, which is renamed
+/// to `target` once the download is complete. An interrupted download
+/// is resumed from where it has stopped, if the server supports range requests.
+///
+/// If `expected` is given, the SHA-256 digest of the download has to match it.
+/// The temporary file is removed if the download fails.
+pub fn download_to_file(
+    uri: String,
+    target: PathBuf,
+    max_size: Option<u64>,
+    expected: Option<String>,
+) -> impl Future<Item = (), Error = failure::Error> {
+    let part = part_path(&target);
+    let started = File::create(&part).context(format!(
+        "creating the temporary file {}",
+        part.to_string_lossy()
+    ));
+
+    let download_part = part.clone();
+    let failed_part = part.clone();
+    started
+        .map_err(failure::Error::from)
+        .into_future()
+        .and_then(move |_| {
+            future::loop_fn(0, move |resumes| {
+                download_attempt(&uri, &download_part, max_size).then(move |res| match res {
+                    Ok(()) => Ok(Loop::Break(())),
+                    Err(e) => {
+                        if resumes < MAX_RESUMES && is_interruption(&e) {
+                            warn!("The download has been interrupted, resuming: {}", e);
+                            Ok(Loop::Continue(resumes + 1))
+                        } else {
+                            Err(e)
+                        }
+                    }
+                })
+            })
+        })
+        .and_then(move |()| -> Result<(), failure::Error> {
+            if let Some(expected) = expected {
+                let actual = to_hex(&sha256_file(&part)?);
+                if actual != expected {
+                    return Err(ChecksumMismatch {
+                        file: target.to_string_lossy().into_owned(),
+                        expected,
+                        actual,
+                    }
+                    .into());
+                }
+            }
+            fs::rename(&part, &target).context(format!(
+                "renaming {} to {}",
+                part.to_string_lossy(),
+                target.to_string_lossy()
+            ))?;
+            Ok(())
+        })
+        .map_err(move |e| {
+            // The download can't be resumed anymore, so the partial file is useless
+            let _ = fs::remove_file(&failed_part);
+            e
+        })
+}
+
+/// Checks if the download has failed because of the connection,
+/// rather than the response
+fn is_interruption(e: &failure::Error) -> bool {
+    e.iter_chain().any(|cause| {
+        cause.downcast_ref::<PayloadError>().is_some()
+            || cause.downcast_ref::<client::SendRequestError>().is_some()
+    })
+}
+
+/// Downloads the rest of the resource, appending it to `part`
+fn download_attempt(
+    uri: &str,
+    part: &Path,
+    max_size: Option<u64>,
+) -> impl Future<Item = (), Error = failure::Error> {
+    let file = OpenOptions::new()
+        .append(true)
+        .open(part)
+        .and_then(|file| {
+            let offset = file.metadata()?.len();
+            Ok((file, offset))
+        })
+        .context(format!("opening {}", part.to_string_lossy()));
+    let part_name = part.to_string_lossy().into_owned();
+    let (file, offset) = match file {
+        Ok(ret) => ret,
+        Err(e) => return Either::A(future::err(e.into())),
+    };
+
+    let mut request = client::ClientRequest::get(uri);
+    if offset > 0 {
+        request.header(header::RANGE, format!("bytes={}-", offset));
+    }
+    let request = match request.finish() {
+        Ok(request) => request,
+        Err(e) => return Either::A(future::err(format_err!("invalid request: {}", e))),
+    };
+
+    let download = request
+        .send()
+        .context("downloading from the hub")
+        .and_then(move |response| -> Result<_, failure::Error> {
+            let status = response.status();
+            let offset = match status {
+                StatusCode::PARTIAL_CONTENT => offset,
+                // The server has ignored the range, so we start over
+                _ if status.is_success() => {
+                    file.set_len(0)?;
+                    0
+                }
+                _ => return Err(format_err!("the hub has responded with {}", status)),
+            };
+            let total = content_length(&response);
+            if let Some(limit) = max_size {
+                if total > limit {
+                    return Err(SizeLimitExceeded(human_size(limit)).into());
+                }
+            }
+            let name = format!("Downloading {}", part_name);
+            let mut progress = progress::Transfer::new(name, total);
+            progress.advance(offset);
+
+            let written = response
+                .payload()
+                .from_err()
+                .fold((file, offset), move |(mut file, offset), chunk| {
+                    let offset = offset + chunk.len() as u64;
+                    if let Some(limit) = max_size {
+                        if offset > limit {
+                            return Err(SizeLimitExceeded(human_size(limit)).into());
+                        }
+                    }
+                    file.write_all(&chunk)?;
+                    progress.advance(chunk.len() as u64);
+                    Ok::<_, failure::Error>((file, offset))
+                })
+                .and_then(|(file, _)| -> Result<(), failure::Error> {
+                    file.sync_all()?;
+                    Ok(())
+                });
+            Ok(written)
+        })
+        .flatten();
+    Either::B(download)
+}
+
+/// Returns: the total size of the resource, or 0 if unknown
+fn content_length(response: &client::ClientResponse) -> u64 {
+    let header = match response.status() {
+        StatusCode::PARTIAL_CONTENT => response
+            .headers()
+            .get(header::CONTENT_RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|range| range.rsplit('/').next().map(str::to_owned)),
+        _ => response
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned),
+    };
+    header.and_then(|len| len.parse().ok()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_human_size() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(3 << 30), "3.0 GiB");
+    }
+
+    #[test]
+    fn test_part_path() {
+        assert_eq!(
+            part_path(Path::new("out/result.tar.gz")),
+            Path::new("out/result.tar.gz.part")
+        );
+    }
+
+    #[test]
+    fn test_hashed() {
+        let (stream, hasher) = hashed(data_chunks(b"abc".to_vec(), "test"));
+        stream.collect().wait().unwrap();
+        assert_eq!(
+            to_hex(&hasher.replace(Sha256::new()).finish()),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_data_chunks() {
+        let data = vec![7; CHUNK_SIZE * 2 + 10];
+        let chunks = data_chunks(data, "test").collect().wait().unwrap();
+        let lens: Vec<_> = chunks.iter().map(Bytes::len).collect();
+        assert_eq!(lens, vec![CHUNK_SIZE, CHUNK_SIZE, 10]);
+    }
+}